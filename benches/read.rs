@@ -0,0 +1,46 @@
+#![deny(future_incompatible, rust_2018_idioms, trivial_casts, unsafe_code)]
+
+// Compares the throughput of `fs_tracing::File::read` against `std::fs::File::read` on the
+// successful path, where fs-tracing should not create any span.
+
+#[path = "../examples/globals/mod.rs"]
+mod globals;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::io::{Read, Seek, SeekFrom};
+
+const FILE_SIZE: usize = 1 << 20;
+const CHUNK_SIZE: usize = 4096;
+
+fn read_all<R: Read + Seek>(file: &mut R, buf: &mut [u8]) {
+    file.seek(SeekFrom::Start(0)).unwrap();
+    while file.read(buf).unwrap() > 0 {}
+}
+
+fn bench_read(c: &mut Criterion) {
+    globals::install();
+
+    let path = std::env::temp_dir().join(format!("fs-tracing-bench-{}", std::process::id()));
+    std::fs::write(&path, vec![0u8; FILE_SIZE]).unwrap();
+
+    let mut group = c.benchmark_group("File::read");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+
+    group.bench_function("std", |b| {
+        let mut file = std::fs::File::open(&path).unwrap();
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        b.iter(|| read_all(&mut file, &mut buf));
+    });
+
+    group.bench_function("fs_tracing", |b| {
+        let mut file = fs_tracing::File::open(&path).unwrap();
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        b.iter(|| read_all(&mut file, &mut buf));
+    });
+
+    group.finish();
+    std::fs::remove_file(&path).unwrap();
+}
+
+criterion_group!(benches, bench_read);
+criterion_main!(benches);
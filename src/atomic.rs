@@ -0,0 +1,231 @@
+use crate::{File, OpenOptions};
+use std::{
+    error,
+    ffi::OsString,
+    fmt,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tracing::warn;
+
+/// Distinguishes the temporary files created by a process.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes `contents` to the file at `path` atomically, so that readers see either the previous
+/// contents or the new ones and never a partially written file.
+///
+/// See [`AtomicFile`] for the steps involved.
+pub fn write_atomic<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut file = AtomicFile::new(path)?;
+        file.write_all(contents)?;
+        file.commit()
+    }
+
+    write_atomic(path.as_ref(), contents.as_ref())
+}
+
+/// A writer replacing the file at a path atomically on [`commit`](AtomicFile::commit).
+///
+/// The data are written to a temporary file in the same directory as the target, which is synced
+/// and renamed over the target on commit, before the directory itself is synced so that the
+/// rename survives a crash. The permissions of an existing target, but not its owner, are copied to
+/// the temporary file before the rename. If the writer is dropped without being committed, or if
+/// committing fails before the rename, the temporary file is removed and the target is left
+/// untouched. A failure to sync the directory after the rename is returned as a [`SyncDirError`]
+/// instead, since the target already has the new contents.
+///
+/// Each step goes through the wrappers of this crate, so a failure is traced in the span of the
+/// failing step: `OpenOptions::open` for creating the temporary file, `File::write` and its
+/// variants, `metadata` and `File::set_permissions` for copying the permissions, `File::sync_all`
+/// for the temporary file or the directory, and `rename`.
+///
+/// ```no_run
+/// use std::io::Write;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut file = fs_tracing::AtomicFile::new("app.conf")?;
+/// writeln!(file, "verbose = true")?;
+/// file.commit()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AtomicFile {
+    path: PathBuf,
+    // `None` once committed.
+    temp: Option<(File, PathBuf)>,
+}
+
+impl fmt::Debug for AtomicFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AtomicFile")
+            .field("path", &self.path)
+            .field("temp", &self.temp.as_ref().map(|(_, temp)| temp))
+            .finish()
+    }
+}
+
+fn directory(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+impl AtomicFile {
+    /// Creates a temporary file next to `path` to write the new contents of `path` to.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fn new(path: &Path) -> io::Result<AtomicFile> {
+            let mut name = OsString::from(".");
+            name.push(path.file_name().unwrap_or_default());
+            name.push(format!(
+                ".{}.{}.tmp",
+                process::id(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            ));
+            let temp = directory(path).join(name);
+
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp)?;
+
+            Ok(AtomicFile {
+                path: path.to_path_buf(),
+                temp: Some((file, temp)),
+            })
+        }
+
+        new(path.as_ref())
+    }
+
+    /// Returns the path being replaced.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Syncs the written data and replaces the target with them.
+    pub fn commit(mut self) -> io::Result<()> {
+        let (file, temp) = self.temp.take().expect("AtomicFile is committed only once");
+        // the temporary file is removed on failure, as if the writer was dropped.
+        if let Err(e) = Self::replace(file, &temp, &self.path) {
+            Self::remove(&temp);
+            return Err(e);
+        }
+
+        // directories cannot be opened as files on Windows, where renames are durable anyway.
+        #[cfg(unix)]
+        if let Err(error) = File::open(directory(&self.path)).and_then(|dir| dir.sync_all()) {
+            return Err(io::Error::new(
+                error.kind(),
+                SyncDirError {
+                    path: self.path.clone(),
+                    error,
+                },
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn replace(mut file: File, temp: &Path, path: &Path) -> io::Result<()> {
+        file.flush()?;
+        // the temporary file is created with the default permissions, which must not loosen or
+        // tighten the ones of the target.
+        match crate::metadata(path) {
+            Ok(metadata) => file.set_permissions(metadata.permissions())?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        file.sync_all()?;
+        drop(file);
+
+        crate::rename(temp, path)
+    }
+
+    fn remove(temp: &Path) {
+        if let Err(e) = crate::remove_file(temp) {
+            warn!(target: "fs_tracing", ?temp, error = %e, "failed to remove the temporary file");
+        }
+    }
+
+    fn file(&mut self) -> &mut File {
+        match &mut self.temp {
+            Some((file, _)) => file,
+            None => unreachable!("AtomicFile is not used after being committed"),
+        }
+    }
+}
+
+/// The failure of [`AtomicFile::commit`] to sync the directory after replacing the target.
+///
+/// The target already has the new contents, but the replacement may not survive a crash. It is
+/// returned inside an `io::Error` with the kind of the failure, and can be retrieved with
+/// [`io::Error::get_ref`] and `downcast_ref`.
+#[derive(Debug)]
+pub struct SyncDirError {
+    path: PathBuf,
+    error: io::Error,
+}
+
+impl SyncDirError {
+    /// Returns the path which was replaced.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the failure to open or sync the directory, traced in the span of the failing step.
+    pub fn error(&self) -> &io::Error {
+        &self.error
+    }
+}
+
+impl fmt::Display for SyncDirError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} has the new contents, but syncing its directory failed",
+            self.path.display()
+        )?;
+        write!(f, "{}", self.error)
+    }
+}
+
+impl error::Error for SyncDirError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        self.file().write_vectored(bufs)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.file().write_all(buf)
+    }
+
+    fn write_fmt(&mut self, fmt: fmt::Arguments<'_>) -> io::Result<()> {
+        self.file().write_fmt(fmt)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if let Some((file, temp)) = self.temp.take() {
+            drop(file);
+            Self::remove(&temp);
+        }
+    }
+}
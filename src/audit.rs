@@ -0,0 +1,233 @@
+//! Collection of the paths accessed through fs-tracing.
+//!
+//! [`record`] runs a closure and returns every path which the closure accessed through the
+//! wrappers on the current thread, classified by [`Access`]. This is useful for dependency
+//! tracking and reproducibility checks.
+//!
+//! ```
+//! use fs_tracing::audit::{self, Access};
+//! use std::path::Path;
+//!
+//! let (result, log) = audit::record(|| fs_tracing::read("/not_exist"));
+//! assert!(result.is_err());
+//! assert!(log.contains(Path::new("/not_exist"), Access::Read));
+//! ```
+//!
+//! Paths are recorded as they were passed to the wrappers, whether or not the operation succeeded.
+//! Each distinct access is recorded once, so that reading a file in chunks records a single
+//! [`Access::Read`] of it. Accesses made by other threads, including ones spawned by the closure,
+//! are not recorded.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashSet},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The number of recorders active on any thread, so that the wrappers can skip looking up the
+/// thread-local recorders when nobody is recording.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// The distinct accesses of a recording, in the order of their first occurrence.
+#[derive(Debug, Default)]
+struct Entries {
+    ordered: Vec<(PathBuf, Access)>,
+    seen: HashSet<(PathBuf, Access)>,
+}
+
+impl Entries {
+    fn push(&mut self, path: &Path, access: Access) {
+        // repeated calls on the same handle, such as reading in chunks, skip copying the path.
+        if self
+            .ordered
+            .last()
+            .is_some_and(|(last, last_access)| *last_access == access && last == path)
+        {
+            return;
+        }
+
+        // looking up a borrowed key would need a tuple of references, so the path is copied.
+        let entry = (path.to_path_buf(), access);
+        if !self.seen.contains(&entry) {
+            self.seen.insert(entry.clone());
+            self.ordered.push(entry);
+        }
+    }
+}
+
+thread_local! {
+    static RECORDERS: RefCell<Vec<(u64, Entries)>> = const { RefCell::new(Vec::new()) };
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+/// The kind of an access to a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Access {
+    /// The contents of a file or the target of a symbolic link were read.
+    Read,
+    /// The contents or the permissions of a file were written, possibly creating the file.
+    Write,
+    /// A file or a directory was created.
+    Create,
+    /// A file or a directory was removed, or renamed away.
+    Remove,
+    /// The entries of a directory were listed.
+    List,
+    /// The metadata of a path was queried.
+    Metadata,
+}
+
+impl Access {
+    /// Returns whether the access modifies the filesystem.
+    pub fn is_mutating(self) -> bool {
+        match self {
+            Access::Write | Access::Create | Access::Remove => true,
+            Access::Read | Access::List | Access::Metadata => false,
+        }
+    }
+}
+
+/// Records the accesses made by a wrapper.
+pub(crate) fn access(accesses: &[(Access, &Path)]) {
+    if ACTIVE.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    RECORDERS.with(|recorders| {
+        for (_, log) in recorders.borrow_mut().iter_mut() {
+            for &(access, path) in accesses {
+                log.push(path, access);
+            }
+        }
+    });
+}
+
+/// Runs `f` and returns its result together with the paths it accessed on the current thread.
+pub fn record<R, F: FnOnce() -> R>(f: F) -> (R, AccessLog) {
+    let recorder = Recorder::start();
+    let result = f();
+    (result, recorder.finish())
+}
+
+/// A guard recording the paths accessed on the current thread until it is finished or dropped.
+///
+/// Recorders can be nested, in which case an access is recorded by all of them.
+#[derive(Debug)]
+pub struct Recorder {
+    id: u64,
+    // recorders are bound to the thread they were started on.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Recorder {
+    /// Starts recording on the current thread.
+    pub fn start() -> Self {
+        let id = NEXT_ID.with(|next| {
+            next.set(next.get() + 1);
+            next.get()
+        });
+        RECORDERS.with(|recorders| recorders.borrow_mut().push((id, Entries::default())));
+        ACTIVE.fetch_add(1, Ordering::Relaxed);
+
+        Self {
+            id,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Stops recording and returns the paths accessed so far.
+    pub fn finish(self) -> AccessLog {
+        let entries = self.take().ordered;
+        std::mem::forget(self);
+        AccessLog { entries }
+    }
+
+    fn take(&self) -> Entries {
+        ACTIVE.fetch_sub(1, Ordering::Relaxed);
+        RECORDERS.with(|recorders| {
+            let mut recorders = recorders.borrow_mut();
+            match recorders.iter().position(|(id, _)| *id == self.id) {
+                Some(index) => recorders.remove(index).1,
+                None => Entries::default(),
+            }
+        })
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.take();
+    }
+}
+
+/// The paths accessed during a recording, in the order of their first access.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessLog {
+    entries: Vec<(PathBuf, Access)>,
+}
+
+impl AccessLog {
+    /// Returns an iterator over the distinct accesses in the order they were first made.
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, Access)> + '_ {
+        self.entries
+            .iter()
+            .map(|(path, access)| (path.as_path(), *access))
+    }
+
+    /// Returns the distinct paths accessed with `access`.
+    pub fn paths(&self, access: Access) -> BTreeSet<&Path> {
+        self.iter()
+            .filter(|(_, a)| *a == access)
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// Returns whether `path` was accessed with `access`.
+    pub fn contains(&self, path: &Path, access: Access) -> bool {
+        self.iter().any(|(p, a)| p == path && a == access)
+    }
+
+    /// Returns the number of distinct recorded accesses.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether no access was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn chunked_reads_are_recorded_once() {
+        let dir = crate::TempDir::new().unwrap();
+        let path = dir.path().join("data");
+        std::fs::write(&path, [0; 64]).unwrap();
+        let other = dir.path().join("other");
+        std::fs::write(&other, [0; 8]).unwrap();
+
+        let (_, log) = record(|| {
+            let mut file = crate::File::open(&path).unwrap();
+            let mut chunk = [0; 8];
+            while file.read(&mut chunk).unwrap() > 0 {
+                crate::read(&other).unwrap();
+            }
+        });
+
+        let accesses: Vec<_> = log.iter().collect();
+        assert_eq!(
+            accesses,
+            [
+                (path.as_path(), Access::Read),
+                (other.as_path(), Access::Read)
+            ]
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! Helpers for Cargo build scripts.
+//!
+//! [`track`] runs a closure and prints `cargo:rerun-if-changed=<path>` for every file which the
+//! closure read and every directory it listed through the wrappers, so that the build script is
+//! rerun whenever one of its inputs changes.
+//!
+//! ```no_run
+//! // build.rs
+//! fn main() -> std::io::Result<()> {
+//!     let schema = fs_tracing::build_script::track(|| fs_tracing::read_to_string("schema.txt"))?;
+//!     // ...
+//!     # let _ = schema;
+//!     Ok(())
+//! }
+//! ```
+
+use crate::audit::{self, Access, AccessLog};
+use std::{
+    collections::BTreeSet,
+    io::{self, Write},
+    path::Path,
+};
+
+/// Runs `f` and prints `cargo:rerun-if-changed` for the paths it read or listed.
+///
+/// See [`emit`] for the paths being printed.
+pub fn track<R, F: FnOnce() -> R>(f: F) -> R {
+    let (result, log) = audit::record(f);
+    emit(&log);
+    result
+}
+
+/// Prints `cargo:rerun-if-changed` for the paths read or listed in `log`.
+///
+/// Paths which were also written or created in `log` are skipped, since they are outputs of the
+/// build script and would make it rerun on every build.
+pub fn emit(log: &AccessLog) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for path in inputs(log) {
+        // there is nothing meaningful to do if the build script cannot talk to Cargo.
+        let _ = writeln!(stdout, "cargo:rerun-if-changed={}", path.display());
+    }
+}
+
+fn inputs(log: &AccessLog) -> BTreeSet<&Path> {
+    let outputs: BTreeSet<&Path> = log
+        .iter()
+        .filter(|(_, access)| matches!(access, Access::Write | Access::Create))
+        .map(|(path, _)| path)
+        .collect();
+
+    log.iter()
+        .filter(|(_, access)| matches!(access, Access::Read | Access::List))
+        .map(|(path, _)| path)
+        .filter(|path| !outputs.contains(path))
+        .collect()
+}
@@ -0,0 +1,635 @@
+use crate::{audit::Access, Metadata};
+use std::{
+    error, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// What [`copy_dir_all`] does when a destination entry already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overwrite {
+    /// Fails with an `AlreadyExists` error.
+    Fail,
+    /// Leaves the existing entry as is.
+    Skip,
+    /// Replaces the existing entry. Existing directories are merged with the copied ones.
+    Replace,
+}
+
+type Filter = Box<dyn Fn(&Path) -> bool>;
+
+/// Options for [`copy_dir_all`].
+pub struct CopyOptions {
+    overwrite: Overwrite,
+    follow_symlinks: bool,
+    permissions: bool,
+    timestamps: bool,
+    collect_errors: bool,
+    filter: Option<Filter>,
+}
+
+impl fmt::Debug for CopyOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CopyOptions")
+            .field("overwrite", &self.overwrite)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("permissions", &self.permissions)
+            .field("timestamps", &self.timestamps)
+            .field("collect_errors", &self.collect_errors)
+            .field("filter", &self.filter.is_some())
+            .finish()
+    }
+}
+
+impl CopyOptions {
+    /// Creates options failing on existing entries, copying symbolic links as links, and not
+    /// preserving the permissions of directories nor the timestamps.
+    pub fn new() -> Self {
+        Self {
+            overwrite: Overwrite::Fail,
+            follow_symlinks: false,
+            permissions: false,
+            timestamps: false,
+            collect_errors: false,
+            filter: None,
+        }
+    }
+
+    /// Sets what to do when a destination entry already exists.
+    pub fn overwrite(&mut self, overwrite: Overwrite) -> &mut Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Sets whether to copy the targets of symbolic links instead of the links themselves.
+    pub fn follow_symlinks(&mut self, follow: bool) -> &mut Self {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Sets whether to preserve the permissions of the directories.
+    ///
+    /// The permissions of the files are always preserved, as in [`copy`](crate::copy).
+    pub fn preserve_permissions(&mut self, preserve: bool) -> &mut Self {
+        self.permissions = preserve;
+        self
+    }
+
+    /// Sets whether to preserve the access and modification times of the files and directories.
+    pub fn preserve_timestamps(&mut self, preserve: bool) -> &mut Self {
+        self.timestamps = preserve;
+        self
+    }
+
+    /// Sets whether to keep copying the other entries after a failure, and report all the
+    /// failures at the end with [`CopyErrors`].
+    pub fn collect_errors(&mut self, collect: bool) -> &mut Self {
+        self.collect_errors = collect;
+        self
+    }
+
+    /// Copies only the entries for which `filter` returns `true`, given their paths relative to
+    /// the source directory. A directory which is filtered out is skipped with its contents.
+    pub fn filter<F: Fn(&Path) -> bool + 'static>(&mut self, filter: F) -> &mut Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+}
+
+/// The failures collected by [`copy_dir_all`] with [`CopyOptions::collect_errors`].
+///
+/// It is returned inside an `io::Error` with the kind of the first failure, and can be retrieved
+/// with [`io::Error::get_ref`] and `downcast_ref`.
+#[derive(Debug)]
+pub struct CopyErrors {
+    errors: Vec<io::Error>,
+}
+
+impl CopyErrors {
+    /// Returns the failures, each of which is traced with the entry and the failing step.
+    pub fn errors(&self) -> &[io::Error] {
+        &self.errors
+    }
+}
+
+impl fmt::Display for CopyErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} entries failed to be copied", self.errors.len())?;
+        for error in &self.errors {
+            writeln!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for CopyErrors {}
+
+/// Copies the directory `from` to `to` recursively, returning the number of bytes copied.
+///
+/// Copying a directory into itself fails with an `InvalidInput` error before copying anything,
+/// once both paths are resolved. When following symbolic links, a link leading back to a
+/// directory being copied, or to one being created, fails likewise instead of recursing forever;
+/// this is only detected on Unix.
+///
+/// Each failure is traced in a `copy_dir_all` span with the source and destination entries and
+/// the failing `step`, such as `create_dir` or `copy`.
+///
+/// ```no_run
+/// use fs_tracing::{CopyOptions, Overwrite};
+///
+/// # fn main() -> std::io::Result<()> {
+/// fs_tracing::copy_dir_all(
+///     "assets",
+///     "dist/assets",
+///     CopyOptions::new()
+///         .overwrite(Overwrite::Replace)
+///         .filter(|path| path.extension().is_none_or(|extension| extension != "tmp")),
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn copy_dir_all<P: AsRef<Path>, Q: AsRef<Path>>(
+    from: P,
+    to: Q,
+    options: &CopyOptions,
+) -> io::Result<u64> {
+    fn copy_dir_all(from: &Path, to: &Path, options: &CopyOptions) -> io::Result<u64> {
+        let mut progress = Progress::default();
+        copy_tree(from, to, options, &mut progress, false)?;
+        Ok(progress.bytes)
+    }
+
+    copy_dir_all(from.as_ref(), to.as_ref(), options)
+}
+
+/// What a copy completed so far, which is kept when it fails.
+#[derive(Debug, Default)]
+pub(crate) struct Progress {
+    pub(crate) bytes: u64,
+    /// The completely copied entries relative to the source, if tracked.
+    pub(crate) entries: Vec<PathBuf>,
+}
+
+/// Copies the directory `from` to `to`, recording the progress in `progress` and the completely
+/// copied entries if `track_entries` is set.
+pub(crate) fn copy_tree(
+    from: &Path,
+    to: &Path,
+    options: &CopyOptions,
+    progress: &mut Progress,
+    track_entries: bool,
+) -> io::Result<()> {
+    let mut copy = Copy {
+        options,
+        errors: Vec::new(),
+        progress,
+        track_entries,
+        ancestors: Vec::new(),
+    };
+    let metadata = copy.metadata(from, to, true)?;
+    traced!(
+        "copy_dir_all" [Access::Metadata => from, Access::Metadata => to],
+        check_destination(from, to),
+        step = "check",
+        ?from,
+        ?to
+    )?;
+    copy.dir(from, to, Path::new(""), &metadata)?;
+
+    if copy.errors.is_empty() {
+        Ok(())
+    } else {
+        let kind = copy.errors[0].kind();
+        Err(io::Error::new(
+            kind,
+            CopyErrors {
+                errors: copy.errors,
+            },
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    /// Copies to a new entry.
+    Copy,
+    /// Copies the contents of a directory into an existing one.
+    Merge,
+    Skip,
+}
+
+struct Copy<'a> {
+    options: &'a CopyOptions,
+    errors: Vec<io::Error>,
+    progress: &'a mut Progress,
+    track_entries: bool,
+    // the identifiers of the source and destination directories being copied, when following
+    // symbolic links.
+    ancestors: Vec<(u64, u64)>,
+}
+
+impl Copy<'_> {
+    /// Records `error`, or returns it if the errors are not collected.
+    fn fail(&mut self, error: io::Error) -> io::Result<()> {
+        if self.options.collect_errors {
+            self.errors.push(error);
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    /// Returns the metadata of `from`, failing if it is not a directory when `dir` is set.
+    fn metadata(&self, from: &Path, to: &Path, dir: bool) -> io::Result<Metadata> {
+        traced!(
+            "copy_dir_all" [Access::Metadata => from],
+            stat(from, self.options.follow_symlinks, dir).map(|inner| Metadata { inner }),
+            step = "metadata",
+            ?from,
+            ?to
+        )
+    }
+
+    /// Copies the contents of the directory `from` to the directory `to`, which is at `relative`
+    /// from the roots.
+    fn dir(
+        &mut self,
+        from: &Path,
+        to: &Path,
+        relative: &Path,
+        metadata: &Metadata,
+    ) -> io::Result<()> {
+        // the ancestors are restored however the copy of the directory ends.
+        let depth = self.ancestors.len();
+        let result = self.copy_dir(from, to, relative, metadata);
+        self.ancestors.truncate(depth);
+        result
+    }
+
+    fn copy_dir(
+        &mut self,
+        from: &Path,
+        to: &Path,
+        relative: &Path,
+        metadata: &Metadata,
+    ) -> io::Result<()> {
+        let action = match self.prepare(to, true) {
+            Ok(action) => action,
+            Err(e) => return self.fail(e),
+        };
+        if action == Action::Skip {
+            return Ok(());
+        }
+        if action == Action::Copy {
+            if let Err(e) = traced!(
+                "copy_dir_all" [Access::Create => to],
+                fs::create_dir(to),
+                step = "create_dir",
+                ?from,
+                ?to
+            ) {
+                return self.fail(e);
+            }
+        }
+
+        if self.options.follow_symlinks {
+            let created = traced!(
+                "copy_dir_all" [Access::Metadata => to],
+                fs::metadata(to).map(|inner| Metadata { inner }),
+                step = "metadata",
+                ?from,
+                ?to
+            );
+            match created {
+                Ok(created) => self
+                    .ancestors
+                    .extend(id(&metadata.inner).into_iter().chain(id(&created.inner))),
+                Err(e) => return self.fail(e),
+            }
+        }
+
+        let entries = traced!(
+            "copy_dir_all" [Access::List => from],
+            fs::read_dir(from).and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.file_name().into()))
+                    .collect::<io::Result<Vec<PathBuf>>>()
+            }),
+            step = "read_dir",
+            ?from,
+            ?to
+        );
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(e) => return self.fail(e),
+        };
+
+        for name in entries {
+            let relative = relative.join(&name);
+            if let Some(filter) = &self.options.filter {
+                if !filter(&relative) {
+                    continue;
+                }
+            }
+            self.entry(&from.join(&name), &to.join(&name), &relative)?;
+        }
+
+        self.attributes(from, to, metadata, self.options.permissions)
+    }
+
+    fn entry(&mut self, from: &Path, to: &Path, relative: &Path) -> io::Result<()> {
+        let errors = self.errors.len();
+        self.copy_entry(from, to, relative)?;
+        if self.track_entries && self.errors.len() == errors {
+            self.progress.entries.push(relative.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn copy_entry(&mut self, from: &Path, to: &Path, relative: &Path) -> io::Result<()> {
+        let metadata = match self.metadata(from, to, false) {
+            Ok(metadata) => metadata,
+            Err(e) => return self.fail(e),
+        };
+        if metadata.is_dir() {
+            if let Err(e) = traced!(
+                span_only "copy_dir_all",
+                self.check_loop(&metadata),
+                step = "loop",
+                ?from,
+                ?to
+            ) {
+                return self.fail(e);
+            }
+            return self.dir(from, to, relative, &metadata);
+        }
+
+        match self.prepare(to, false) {
+            Ok(Action::Copy) => {}
+            Ok(_) => return Ok(()),
+            Err(e) => return self.fail(e),
+        }
+
+        if metadata.file_type().is_symlink() {
+            if let Err(e) = traced!(
+                "copy_dir_all" [Access::Read => from, Access::Create => to],
+                fs::read_link(from).and_then(|target| symlink(&target, to)),
+                step = "symlink",
+                ?from,
+                ?to
+            ) {
+                return self.fail(e);
+            }
+            return Ok(());
+        }
+
+        // a retry would fail on the file created by the failed attempt, hiding its error.
+        match traced!(
+            once "copy_dir_all" [Access::Read => from, Access::Write => to] => read(|n| *n) => written(|n| *n),
+            copy_file(from, to, self.options.overwrite),
+            step = "copy",
+            ?from,
+            ?to
+        ) {
+            Ok(copied) => self.progress.bytes += copied,
+            Err(e) => return self.fail(e),
+        }
+
+        // the permissions of files are copied by `fs::copy`.
+        self.attributes(from, to, &metadata, false)
+    }
+
+    /// Fails if the directory of `metadata` is being copied or created, which means that a
+    /// followed symbolic link leads back to it.
+    fn check_loop(&self, metadata: &Metadata) -> io::Result<()> {
+        if id(&metadata.inner).is_some_and(|id| self.ancestors.contains(&id)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the symbolic link leads back to a directory being copied",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Handles an existing entry at `to` before copying a directory or a file to it.
+    ///
+    /// With [`Overwrite::Fail`], the copying steps fail on existing entries by themselves.
+    fn prepare(&self, to: &Path, dir: bool) -> io::Result<Action> {
+        let existing = match fs::symlink_metadata(to) {
+            Ok(existing) => existing,
+            // the copying steps report the other errors.
+            Err(_) => return Ok(Action::Copy),
+        };
+
+        match self.options.overwrite {
+            Overwrite::Fail => Ok(Action::Copy),
+            Overwrite::Skip | Overwrite::Replace if dir && existing.is_dir() => Ok(Action::Merge),
+            Overwrite::Skip => Ok(Action::Skip),
+            Overwrite::Replace => {
+                traced!(
+                    "copy_dir_all" [Access::Remove => to],
+                    remove(to, existing.is_dir()),
+                    step = "overwrite",
+                    ?to
+                )?;
+                Ok(Action::Copy)
+            }
+        }
+    }
+
+    fn attributes(
+        &mut self,
+        from: &Path,
+        to: &Path,
+        metadata: &Metadata,
+        permissions: bool,
+    ) -> io::Result<()> {
+        if permissions {
+            if let Err(e) = traced!(
+                "copy_dir_all" [Access::Write => to],
+                fs::set_permissions(to, metadata.inner.permissions()),
+                step = "set_permissions",
+                ?from,
+                ?to
+            ) {
+                return self.fail(e);
+            }
+        }
+
+        if self.options.timestamps {
+            if let Err(e) = traced!(
+                "copy_dir_all" [Access::Write => to],
+                set_times(to, &metadata.inner),
+                step = "set_times",
+                ?from,
+                ?to
+            ) {
+                return self.fail(e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn stat(path: &Path, follow_symlinks: bool, dir: bool) -> io::Result<fs::Metadata> {
+    let metadata = if follow_symlinks {
+        fs::metadata(path)?
+    } else {
+        fs::symlink_metadata(path)?
+    };
+    if dir && !metadata.is_dir() {
+        return Err(io::Error::from(io::ErrorKind::NotADirectory));
+    }
+    Ok(metadata)
+}
+
+/// Fails if `to` is `from` or inside it, once both are resolved.
+fn check_destination(from: &Path, to: &Path) -> io::Result<()> {
+    if resolve(to)?.starts_with(fs::canonicalize(from)?) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the destination is inside the source directory",
+        ));
+    }
+    Ok(())
+}
+
+/// Resolves `path`, which may not exist yet, by canonicalizing its nearest existing ancestor.
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+    loop {
+        match fs::canonicalize(existing) {
+            Ok(resolved) => {
+                return Ok(missing
+                    .iter()
+                    .rev()
+                    .fold(resolved, |path, name| path.join(name)))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                match (existing.parent(), existing.file_name()) {
+                    (Some(parent), Some(name)) => {
+                        missing.push(name);
+                        existing = if parent.as_os_str().is_empty() {
+                            Path::new(".")
+                        } else {
+                            parent
+                        };
+                    }
+                    _ => return Err(e),
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Returns the device and inode numbers identifying the entry of `metadata`.
+#[cfg(unix)]
+fn id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+fn remove(path: &Path, dir: bool) -> io::Result<()> {
+    if dir {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Copies a file, failing if `to` exists unless overwriting.
+fn copy_file(from: &Path, to: &Path, overwrite: Overwrite) -> io::Result<u64> {
+    if overwrite == Overwrite::Fail && fs::symlink_metadata(to).is_ok() {
+        return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+    }
+    fs::copy(from, to)
+}
+
+pub(crate) fn set_times(path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    let times = fs::FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+    fs::File::open(path)?.set_times(times)
+}
+
+#[cfg(unix)]
+pub(crate) fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+pub(crate) fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    let dir = link
+        .parent()
+        .map(|parent| parent.join(target))
+        .and_then(|target| fs::metadata(target).ok())
+        .is_some_and(|metadata| metadata.is_dir());
+    if dir {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(temp: &crate::TempDir) -> PathBuf {
+        let from = temp.path().join("from");
+        fs::create_dir_all(from.join("sub")).unwrap();
+        fs::write(from.join("sub/file"), "file").unwrap();
+        from
+    }
+
+    #[test]
+    fn copying_into_itself_fails_before_copying() {
+        let temp = crate::TempDir::new().unwrap();
+        let from = source(&temp);
+
+        for to in [
+            from.clone(),
+            from.join("sub/to"),
+            from.join("sub/../../from/to"),
+        ] {
+            let e = copy_dir_all(&from, &to, &CopyOptions::new()).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{}", to.display());
+        }
+        assert!(!from.join("sub/to").exists());
+        assert!(!from.join("to").exists());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&from, temp.path().join("alias")).unwrap();
+            let to = temp.path().join("alias/to");
+            let e = copy_dir_all(&from, &to, &CopyOptions::new()).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+            assert!(!from.join("to").exists());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn followed_symlink_loops_are_detected() {
+        let temp = crate::TempDir::new().unwrap();
+        let from = source(&temp);
+        std::os::unix::fs::symlink("..", from.join("sub/loop")).unwrap();
+
+        let to = temp.path().join("to");
+        let e = copy_dir_all(&from, &to, CopyOptions::new().follow_symlinks(true)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+
+        // without following, the link is copied as a link.
+        let to = temp.path().join("links");
+        assert_eq!(copy_dir_all(&from, &to, &CopyOptions::new()).unwrap(), 4);
+        assert_eq!(fs::read_link(to.join("sub/loop")).unwrap(), Path::new(".."));
+        assert_eq!(fs::read(to.join("sub/file")).unwrap(), b"file");
+    }
+}
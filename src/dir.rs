@@ -0,0 +1,610 @@
+use crate::{audit::Access, File, Metadata, OpenOptions};
+use nix::{
+    errno::Errno,
+    fcntl::{self, AtFlags, OFlag, OpenHow, ResolveFlag},
+    sys::stat::{self, Mode, SFlag},
+    unistd::{self, UnlinkatFlags},
+};
+use std::{
+    ffi::{OsStr, OsString},
+    fmt, fs,
+    io::{self, Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::{ffi::OsStrExt, fs::OpenOptionsExt},
+    },
+    path::{Component, Path, PathBuf},
+};
+
+/// A directory handle restricting the operations to the paths beneath it.
+///
+/// The handle keeps its root directory open, and the paths passed to it are resolved by the kernel
+/// relative to the root with `openat2(RESOLVE_BENEATH)`. Absolute paths, `..` components leaving
+/// the root, and symbolic links which are absolute or lead outside of the root are refused with a
+/// `PermissionDenied` error, traced in the span of the operation with the root and the offending
+/// path. Since the operations are made relative to open directories rather than to paths, a
+/// concurrent process replacing a directory with a symbolic link cannot escape the root either.
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// let dir = fs_tracing::Dir::open_root(std::env::temp_dir())?;
+///
+/// let e = dir.read("../etc/passwd").unwrap_err();
+/// assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// `Dir` is only available on Linux, and fails with `ENOSYS` on kernels older than 5.6 which do
+/// not have `openat2`.
+pub struct Dir {
+    root: PathBuf,
+    fd: OwnedFd,
+}
+
+impl fmt::Debug for Dir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.root.fmt(f)
+    }
+}
+
+fn escape(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("{} escapes the root directory", path.display()),
+    )
+}
+
+/// Takes the ownership of a file descriptor newly opened by nix.
+#[allow(unsafe_code)]
+fn owned(fd: RawFd) -> OwnedFd {
+    // SAFETY: `fd` was just opened and is not owned by anything else.
+    unsafe { OwnedFd::from_raw_fd(fd) }
+}
+
+/// Opens `path` beneath the directory `dirfd`, refusing the paths which escape it.
+fn open_beneath(dirfd: &OwnedFd, path: &Path, flags: OFlag, mode: Mode) -> io::Result<OwnedFd> {
+    // `openat2` refuses a mode unless creating a file.
+    let mode = if flags.contains(OFlag::O_CREAT) {
+        mode
+    } else {
+        Mode::empty()
+    };
+    let how = OpenHow::new()
+        .flags(flags | OFlag::O_CLOEXEC)
+        .mode(mode)
+        .resolve(ResolveFlag::RESOLVE_BENEATH | ResolveFlag::RESOLVE_NO_MAGICLINKS);
+    // an empty path names the directory itself.
+    let target = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+    match fcntl::openat2(dirfd.as_raw_fd(), target, how) {
+        Ok(fd) => Ok(owned(fd)),
+        Err(Errno::EXDEV) => Err(escape(path)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Translates `options` into the flags of `open(2)`.
+fn flags(options: &OpenOptions) -> io::Result<OFlag> {
+    let mut flags = match (options.read, options.write || options.append) {
+        (true, false) => OFlag::O_RDONLY,
+        (false, true) => OFlag::O_WRONLY,
+        (true, true) => OFlag::O_RDWR,
+        (false, false) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the options neither read nor write",
+            ))
+        }
+    };
+    // like std, the custom flags cannot change the access mode.
+    flags |= OFlag::from_bits_truncate(options.custom_flags) & !OFlag::O_ACCMODE;
+    if options.append {
+        flags |= OFlag::O_APPEND;
+    }
+    if options.truncate {
+        flags |= OFlag::O_TRUNC;
+    }
+    if options.create_new {
+        flags |= OFlag::O_CREAT | OFlag::O_EXCL;
+    } else if options.create {
+        flags |= OFlag::O_CREAT;
+    }
+    Ok(flags)
+}
+
+/// Returns the names of the entries of the directory `fd`, except `.` and `..`.
+fn names(fd: &OwnedFd) -> io::Result<Vec<OsString>> {
+    let mut dir = nix::dir::Dir::from(fd.try_clone()?)?;
+    let mut names = Vec::new();
+    for entry in dir.iter() {
+        let name = OsStr::from_bytes(entry?.file_name().to_bytes()).to_os_string();
+        if name != "." && name != ".." {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+/// Removes the entry `name` of the directory `dirfd` with its contents, without following
+/// symbolic links.
+fn remove_all_at(dirfd: &OwnedFd, name: &OsStr) -> io::Result<()> {
+    let stat = stat::fstatat(Some(dirfd.as_raw_fd()), name, AtFlags::AT_SYMLINK_NOFOLLOW)?;
+    if stat.st_mode & SFlag::S_IFMT.bits() != SFlag::S_IFDIR.bits() {
+        unistd::unlinkat(Some(dirfd.as_raw_fd()), name, UnlinkatFlags::NoRemoveDir)?;
+        return Ok(());
+    }
+
+    // a directory replaced with a symbolic link in the meantime fails to open.
+    let dir = open_beneath(
+        dirfd,
+        Path::new(name),
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW,
+        Mode::empty(),
+    )?;
+    for entry in names(&dir)? {
+        remove_all_at(&dir, &entry)?;
+    }
+    unistd::unlinkat(Some(dirfd.as_raw_fd()), name, UnlinkatFlags::RemoveDir)?;
+    Ok(())
+}
+
+impl Dir {
+    /// Opens the directory at `path` as a root.
+    pub fn open_root<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fn open_root(path: &Path) -> io::Result<Dir> {
+            traced!(
+                "Dir::open_root" [Access::Metadata => path],
+                fs::canonicalize(path).and_then(|root| {
+                    let fd = fs::OpenOptions::new()
+                        .read(true)
+                        .custom_flags(OFlag::O_DIRECTORY.bits())
+                        .open(&root)?;
+                    Ok(Dir {
+                        root,
+                        fd: fd.into(),
+                    })
+                }),
+                ?path
+            )
+        }
+
+        open_root(path.as_ref())
+    }
+
+    /// Returns the path of the root directory, which is only used to report the accessed paths.
+    ///
+    /// It is the canonicalized path given to [`Dir::open_root`], joined with the paths given to
+    /// [`Dir::open_dir`].
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Opens the parent directory of `path` beneath the root, and returns it with the last
+    /// component of `path`.
+    fn parent<'a>(&self, path: &'a Path) -> io::Result<(OwnedFd, &'a OsStr)> {
+        if path.has_root() {
+            return Err(escape(path));
+        }
+        match path.components().next_back() {
+            Some(Component::Normal(name)) => {
+                let parent = open_beneath(
+                    &self.fd,
+                    path.parent().unwrap_or_else(|| Path::new("")),
+                    OFlag::O_PATH | OFlag::O_DIRECTORY,
+                    Mode::empty(),
+                )?;
+                Ok((parent, name))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} does not end with a name", path.display()),
+            )),
+        }
+    }
+
+    fn mkdir(&self, path: &Path, mode: Option<u32>) -> io::Result<()> {
+        let (parent, name) = self.parent(path)?;
+        let mode = Mode::from_bits_truncate(mode.unwrap_or(0o777));
+        stat::mkdirat(Some(parent.as_raw_fd()), name, mode)?;
+        Ok(())
+    }
+
+    /// Creates `path` and its missing ancestors beneath the root, with `mode` if given.
+    fn mkdir_all(&self, path: &Path, mode: Option<u32>) -> io::Result<()> {
+        let mut prefix = PathBuf::new();
+        for component in path.components() {
+            prefix.push(component);
+            if let Component::Normal(_) = component {
+                match self.mkdir(&prefix, mode) {
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                    result => result?,
+                }
+            }
+        }
+        // the last component may exist as something else than a directory.
+        open_beneath(
+            &self.fd,
+            path,
+            OFlag::O_PATH | OFlag::O_DIRECTORY,
+            Mode::empty(),
+        )
+        .map(drop)
+    }
+
+    /// Opens a directory beneath the root as a new root.
+    pub fn open_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<Self> {
+        fn open_dir(this: &Dir, path: &Path) -> io::Result<Dir> {
+            let root = this.root.join(path);
+            traced!(
+                "Dir::open_dir" [Access::Metadata => &root],
+                open_beneath(
+                    &this.fd,
+                    path,
+                    OFlag::O_RDONLY | OFlag::O_DIRECTORY,
+                    Mode::empty()
+                )
+                .map(|fd| Dir {
+                    root: root.clone(),
+                    fd
+                }),
+                self = ?this,
+                ?path
+            )
+        }
+
+        open_dir(self, path.as_ref())
+    }
+
+    /// Opens a file beneath the root in read-only mode, like [`File::open`].
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
+        self.open_with(path, OpenOptions::new().read(true))
+    }
+
+    /// Opens a file beneath the root in write-only mode, like [`File::create`].
+    pub fn create<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
+        self.open_with(
+            path,
+            OpenOptions::new().write(true).create(true).truncate(true),
+        )
+    }
+
+    /// Opens a file beneath the root with `options`, like [`OpenOptions::open`].
+    pub fn open_with<P: AsRef<Path>>(&self, path: P, options: &OpenOptions) -> io::Result<File> {
+        fn open_with(this: &Dir, path: &Path, options: &OpenOptions) -> io::Result<File> {
+            if let Some(mode) = options.parents {
+                if let Some(parent) = path.parent() {
+                    this.mkdir_all(parent, mode)?;
+                }
+            }
+
+            let full = this.root.join(path);
+            traced!(
+                "Dir::open" [options.access() => &full],
+                flags(options)
+                    .and_then(|flags| {
+                        let mode = Mode::from_bits_truncate(options.mode);
+                        open_beneath(&this.fd, path, flags, mode)
+                    })
+                    .map(|fd| File {
+                        inner: fd.into(),
+                        path: full.clone(),
+                    }),
+                self = ?this,
+                ?path,
+                ?options
+            )
+        }
+
+        open_with(self, path.as_ref(), options)
+    }
+
+    /// Like [`create_dir`](crate::create_dir), beneath the root.
+    pub fn create_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fn create_dir(this: &Dir, path: &Path) -> io::Result<()> {
+            traced!(
+                "Dir::create_dir" [Access::Create => &this.root.join(path)],
+                this.mkdir(path, None),
+                self = ?this,
+                ?path
+            )
+        }
+
+        create_dir(self, path.as_ref())
+    }
+
+    /// Like [`create_dir_all`](crate::create_dir_all), beneath the root.
+    pub fn create_dir_all<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fn create_dir_all(this: &Dir, path: &Path) -> io::Result<()> {
+            traced!(
+                "Dir::create_dir_all" [Access::Create => &this.root.join(path)],
+                this.mkdir_all(path, None),
+                self = ?this,
+                ?path
+            )
+        }
+
+        create_dir_all(self, path.as_ref())
+    }
+
+    /// Like [`metadata`](crate::metadata), beneath the root.
+    pub fn metadata<P: AsRef<Path>>(&self, path: P) -> io::Result<Metadata> {
+        fn metadata(this: &Dir, path: &Path) -> io::Result<Metadata> {
+            traced!(
+                "Dir::metadata" [Access::Metadata => &this.root.join(path)],
+                open_beneath(&this.fd, path, OFlag::O_PATH, Mode::empty())
+                    .and_then(|fd| fs::File::from(fd).metadata())
+                    .map(|inner| Metadata { inner }),
+                self = ?this,
+                ?path
+            )
+        }
+
+        metadata(self, path.as_ref())
+    }
+
+    /// Like [`symlink_metadata`](crate::symlink_metadata), beneath the root.
+    pub fn symlink_metadata<P: AsRef<Path>>(&self, path: P) -> io::Result<Metadata> {
+        fn symlink_metadata(this: &Dir, path: &Path) -> io::Result<Metadata> {
+            traced!(
+                "Dir::symlink_metadata" [Access::Metadata => &this.root.join(path)],
+                open_beneath(
+                    &this.fd,
+                    path,
+                    OFlag::O_PATH | OFlag::O_NOFOLLOW,
+                    Mode::empty()
+                )
+                .and_then(|fd| fs::File::from(fd).metadata())
+                .map(|inner| Metadata { inner }),
+                self = ?this,
+                ?path
+            )
+        }
+
+        symlink_metadata(self, path.as_ref())
+    }
+
+    /// Like [`read`](crate::read), beneath the root.
+    pub fn read<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        self.open(path)?.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    /// Like [`read_to_string`](crate::read_to_string), beneath the root.
+    pub fn read_to_string<P: AsRef<Path>>(&self, path: P) -> io::Result<String> {
+        let mut contents = String::new();
+        self.open(path)?.read_to_string(&mut contents)?;
+        Ok(contents)
+    }
+
+    /// Like [`write`](crate::write), beneath the root.
+    pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(&self, path: P, contents: C) -> io::Result<()> {
+        self.create(path)?.write_all(contents.as_ref())
+    }
+
+    /// Returns the paths of the entries of a directory beneath the root, like
+    /// [`FileSystem::read_dir`](crate::vfs::FileSystem::read_dir).
+    ///
+    /// The paths are `path` joined with the names of the entries, so they can be passed to the
+    /// other methods of the handle.
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<PathBuf>> {
+        fn read_dir(this: &Dir, path: &Path) -> io::Result<Vec<PathBuf>> {
+            traced!(
+                "Dir::read_dir" [Access::List => &this.root.join(path)],
+                open_beneath(
+                    &this.fd,
+                    path,
+                    OFlag::O_RDONLY | OFlag::O_DIRECTORY,
+                    Mode::empty()
+                )
+                .and_then(|fd| names(&fd))
+                .map(|names| names.iter().map(|name| path.join(name)).collect()),
+                self = ?this,
+                ?path
+            )
+        }
+
+        read_dir(self, path.as_ref())
+    }
+
+    /// Like [`remove_file`](crate::remove_file), beneath the root.
+    pub fn remove_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fn remove_file(this: &Dir, path: &Path) -> io::Result<()> {
+            traced!(
+                "Dir::remove_file" [Access::Remove => &this.root.join(path)],
+                this.parent(path).and_then(|(parent, name)| {
+                    unistd::unlinkat(Some(parent.as_raw_fd()), name, UnlinkatFlags::NoRemoveDir)
+                        .map_err(io::Error::from)
+                }),
+                self = ?this,
+                ?path
+            )
+        }
+
+        remove_file(self, path.as_ref())
+    }
+
+    /// Like [`remove_dir`](crate::remove_dir), beneath the root.
+    pub fn remove_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fn remove_dir(this: &Dir, path: &Path) -> io::Result<()> {
+            traced!(
+                "Dir::remove_dir" [Access::Remove => &this.root.join(path)],
+                this.parent(path).and_then(|(parent, name)| {
+                    unistd::unlinkat(Some(parent.as_raw_fd()), name, UnlinkatFlags::RemoveDir)
+                        .map_err(io::Error::from)
+                }),
+                self = ?this,
+                ?path
+            )
+        }
+
+        remove_dir(self, path.as_ref())
+    }
+
+    /// Like [`remove_dir_all`](crate::remove_dir_all), beneath the root.
+    pub fn remove_dir_all<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fn remove_dir_all(this: &Dir, path: &Path) -> io::Result<()> {
+            traced!(
+                "Dir::remove_dir_all" [Access::Remove => &this.root.join(path)],
+                this.parent(path)
+                    .and_then(|(parent, name)| remove_all_at(&parent, name)),
+                self = ?this,
+                ?path
+            )
+        }
+
+        remove_dir_all(self, path.as_ref())
+    }
+
+    /// Like [`rename`](crate::rename), from beneath the root to beneath the root of `to_dir`.
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        from: P,
+        to_dir: &Dir,
+        to: Q,
+    ) -> io::Result<()> {
+        fn rename(this: &Dir, from: &Path, to_dir: &Dir, to: &Path) -> io::Result<()> {
+            traced!(
+                "Dir::rename" [
+                    Access::Remove => &this.root.join(from),
+                    Access::Write => &to_dir.root.join(to),
+                ],
+                this.parent(from).and_then(|(from_parent, from_name)| {
+                    let (to_parent, to_name) = to_dir.parent(to)?;
+                    fcntl::renameat(
+                        Some(from_parent.as_raw_fd()),
+                        from_name,
+                        Some(to_parent.as_raw_fd()),
+                        to_name,
+                    )
+                    .map_err(io::Error::from)
+                }),
+                self = ?this,
+                ?from,
+                ?to_dir,
+                ?to
+            )
+        }
+
+        rename(self, from.as_ref(), to_dir, to.as_ref())
+    }
+
+    /// Like [`copy`](crate::copy), from beneath the root to beneath the root of `to_dir`.
+    pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        from: P,
+        to_dir: &Dir,
+        to: Q,
+    ) -> io::Result<u64> {
+        let mut source = self.open(from)?;
+        let mut target = to_dir.create(to)?;
+        let copied = io::copy(&mut source, &mut target)?;
+        target.set_permissions(source.metadata()?.permissions())?;
+        Ok(copied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// Creates a temporary directory with a `root` to open and an `outside` sibling holding a
+    /// `secret` file.
+    fn tree() -> (crate::TempDir, Dir, PathBuf) {
+        let temp = crate::TempDir::new().unwrap();
+        let root = temp.path().join("root");
+        let outside = temp.path().join("outside");
+        fs::create_dir(&root).unwrap();
+        fs::create_dir(&outside).unwrap();
+        fs::write(outside.join("secret"), "secret").unwrap();
+        let dir = Dir::open_root(&root).unwrap();
+        (temp, dir, outside)
+    }
+
+    fn assert_untouched(outside: &Path) {
+        let names: Vec<_> = fs::read_dir(outside)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["secret"]);
+        assert_eq!(fs::read(outside.join("secret")).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn absolute_symlinks_are_refused() {
+        let (_temp, dir, outside) = tree();
+        symlink("/etc", dir.root().join("esc")).unwrap();
+        symlink(&outside, dir.root().join("out")).unwrap();
+
+        let e = dir.read("esc/passwd").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        let e = dir.write("out/new", "new").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        let e = dir.remove_file("out/secret").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert_untouched(&outside);
+    }
+
+    #[test]
+    fn relative_symlinks_leaving_the_root_are_refused() {
+        let (_temp, dir, outside) = tree();
+        fs::create_dir(dir.root().join("sub")).unwrap();
+        symlink("../../outside", dir.root().join("sub/esc")).unwrap();
+        symlink("sub", dir.root().join("inside")).unwrap();
+
+        let e = dir.read("sub/esc/secret").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        let e = dir.write("sub/esc/new", "new").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        let e = dir.create_dir("sub/esc/new").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert_untouched(&outside);
+
+        // relative symbolic links staying beneath the root are followed.
+        dir.write("inside/file", "file").unwrap();
+        assert_eq!(dir.read("sub/file").unwrap(), b"file");
+    }
+
+    #[test]
+    fn magic_links_are_refused() {
+        let loop_kind = io::Error::from(Errno::ELOOP).kind();
+        let dir = Dir::open_root("/proc/self").unwrap();
+        let e = dir.read("root/etc/passwd").unwrap_err();
+        assert_eq!(e.kind(), loop_kind);
+        let e = dir.open_dir("cwd").unwrap_err();
+        assert_eq!(e.kind(), loop_kind);
+    }
+
+    #[test]
+    fn remove_dir_all_unlinks_symlinks() {
+        let (_temp, dir, outside) = tree();
+        fs::create_dir(dir.root().join("sub")).unwrap();
+        symlink(&outside, dir.root().join("sub/link")).unwrap();
+        symlink("../../outside", dir.root().join("link")).unwrap();
+
+        dir.remove_dir_all("link").unwrap();
+        dir.remove_dir_all("sub").unwrap();
+        assert!(dir.read_dir("").unwrap().is_empty());
+        assert_untouched(&outside);
+    }
+
+    #[test]
+    fn create_dir_all_refuses_parent_components_leaving_the_root() {
+        let (_temp, dir, outside) = tree();
+
+        let e = dir.create_dir_all("a/../../outside/new").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        let e = dir.create_dir_all("../outside/new").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert_untouched(&outside);
+
+        // `..` components staying beneath the root are allowed.
+        dir.create_dir_all("a/../b/c").unwrap();
+        assert!(dir.metadata("b/c").unwrap().is_dir());
+    }
+}
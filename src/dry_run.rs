@@ -0,0 +1,107 @@
+//! Dry-run mode for the mutating operations.
+//!
+//! In dry-run mode, the wrapped calls which would modify the filesystem (writing, creating,
+//! removing, renaming, copying, or changing permissions) are not performed. Instead, they emit an
+//! `INFO` event with the operation name and the same fields that would be recorded on error, and
+//! return success. The other calls keep running normally.
+//!
+//! ```
+//! let _guard = fs_tracing::dry_run::enter();
+//!
+//! fs_tracing::remove_dir_all("/important").unwrap();
+//! fs_tracing::write("/etc/app.conf", "verbose = true").unwrap();
+//! ```
+//!
+//! Opening a file for writing returns a file backed by the null device, so that writing to it
+//! succeeds without effect. The writes to the files opened before the dry run started are skipped
+//! like the other mutating calls, including `flush`, `sync_all` and `set_len`: `write` and
+//! `write_vectored` report the whole buffer as written. `copy` returns the length of the source as
+//! the number of bytes copied.
+
+use crate::{audit::Access, output::Output};
+use std::{
+    cell::Cell,
+    io,
+    path::Path,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+static GLOBAL: AtomicBool = AtomicBool::new(false);
+
+/// The number of threads in a dry run, so that the wrappers can skip looking up the thread-local
+/// state when there are none.
+static SCOPED: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Enables or disables the dry-run mode for all the threads.
+pub fn set_enabled(enabled: bool) {
+    GLOBAL.store(enabled, Ordering::Relaxed);
+}
+
+/// Enables the dry-run mode for the current thread until the returned guard is dropped.
+pub fn enter() -> DryRunGuard {
+    DEPTH.with(|depth| {
+        if depth.get() == 0 {
+            SCOPED.fetch_add(1, Ordering::Relaxed);
+        }
+        depth.set(depth.get() + 1);
+    });
+
+    DryRunGuard {
+        _not_send: std::marker::PhantomData,
+    }
+}
+
+/// Returns whether the dry-run mode is enabled for the current thread.
+pub fn is_enabled() -> bool {
+    GLOBAL.load(Ordering::Relaxed)
+        || (SCOPED.load(Ordering::Relaxed) > 0 && DEPTH.with(|depth| depth.get() > 0))
+}
+
+/// A guard ending the dry run of the current thread when dropped.
+#[derive(Debug)]
+#[must_use = "the dry run ends when the guard is dropped"]
+pub struct DryRunGuard {
+    // the dry run is bound to the thread which entered it.
+    _not_send: std::marker::PhantomData<*const ()>,
+}
+
+impl Drop for DryRunGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| {
+            depth.set(depth.get() - 1);
+            if depth.get() == 0 {
+                SCOPED.fetch_sub(1, Ordering::Relaxed);
+            }
+        });
+    }
+}
+
+/// Returns the result of skipping a call on `accesses` if it is mutating and the dry-run mode is
+/// enabled.
+pub(crate) fn skip<T: Output>(accesses: &[(Access, &Path)]) -> Option<io::Result<T>> {
+    if !skips(accesses) {
+        return None;
+    }
+
+    T::skipped(accesses)
+}
+
+/// Like [`skip`], but returns `skipped()` instead of the default result of `T`.
+pub(crate) fn skip_with<T>(
+    accesses: &[(Access, &Path)],
+    skipped: impl FnOnce() -> T,
+) -> Option<io::Result<T>> {
+    if !skips(accesses) {
+        return None;
+    }
+
+    Some(Ok(skipped()))
+}
+
+fn skips(accesses: &[(Access, &Path)]) -> bool {
+    accesses.iter().any(|(access, _)| access.is_mutating()) && is_enabled()
+}
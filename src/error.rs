@@ -2,7 +2,8 @@ use std::{error, fmt, io};
 
 #[derive(Debug)]
 pub struct Error {
-    message: String,
+    /// The message of an OS error, which has no source to print it instead.
+    message: Option<String>,
     source: Option<Box<dyn error::Error + Send + Sync + 'static>>,
     span: tracing_error::SpanTrace,
 }
@@ -10,7 +11,9 @@ pub struct Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // CR pandaman: more nice message?
-        writeln!(f, "{}", self.message)?;
+        if let Some(message) = &self.message {
+            writeln!(f, "{}", message)?;
+        }
 
         if let Some(source) = &self.source {
             writeln!(f, "{}", source)?;
@@ -33,9 +36,23 @@ impl error::Error for Error {
 }
 
 impl Error {
+    #[cfg(feature = "testing")]
+    pub(crate) fn span_trace(&self) -> &tracing_error::SpanTrace {
+        &self.span
+    }
+
+    /// Returns whether `error` was already wrapped by this crate.
+    pub(crate) fn is_wrapped(error: &io::Error) -> bool {
+        error.get_ref().is_some_and(|inner| inner.is::<Error>())
+    }
+
     pub(crate) fn wrap_std(source: io::Error) -> io::Error {
         let kind = source.kind();
-        let message = source.to_string();
+        // the message of a custom error is the one of its inner error, printed as the source.
+        let message = match source.get_ref() {
+            Some(_) => None,
+            None => Some(source.to_string()),
+        };
         let source = source.into_inner();
 
         io::Error::new(
@@ -0,0 +1,275 @@
+//! Fault injection for testing error handling.
+//!
+//! A [`Fault`] makes the matching wrapped calls fail without performing them. Faults match on the
+//! operation name (as in the spans, such as `File::write_all` or `rename`), on a glob pattern
+//! against the paths of the call, and on the number of matching calls so far. The injected errors
+//! are wrapped just like real ones, so they carry the same span trace.
+//!
+//! ```
+//! use fs_tracing::fault::Fault;
+//! use std::io::ErrorKind;
+//!
+//! let _guard = Fault::new(ErrorKind::PermissionDenied)
+//!     .operation("rename")
+//!     .path("/tmp/*.conf")
+//!     .inject();
+//!
+//! let e = fs_tracing::rename("/tmp/a.conf", "/tmp/b.conf").unwrap_err();
+//! assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+//! ```
+//!
+//! A fault stays in effect until its [`FaultGuard`] is dropped.
+
+use crate::audit::Access;
+use std::{
+    fmt, io,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+    thread::{self, ThreadId},
+};
+use tracing::debug;
+
+/// The number of injected faults, so that the wrappers can skip looking up the faults when there
+/// are none.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+static FAULTS: Mutex<Vec<Injected>> = Mutex::new(Vec::new());
+
+fn faults() -> MutexGuard<'static, Vec<Injected>> {
+    FAULTS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy, Debug)]
+enum FaultError {
+    Kind(io::ErrorKind),
+    Os(i32),
+}
+
+/// A description of calls to fail and the error to fail them with.
+#[derive(Clone)]
+pub struct Fault {
+    error: FaultError,
+    operation: Option<String>,
+    path: Option<glob::Pattern>,
+    thread: Option<ThreadId>,
+    skip: u64,
+    times: Option<u64>,
+}
+
+impl fmt::Debug for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fault")
+            .field("error", &self.error)
+            .field("operation", &self.operation)
+            .field("path", &self.path.as_ref().map(|path| path.as_str()))
+            .field("thread", &self.thread)
+            .field("skip", &self.skip)
+            .field("times", &self.times)
+            .finish()
+    }
+}
+
+impl Fault {
+    /// Creates a fault failing every call with an error of `kind`.
+    pub fn new(kind: io::ErrorKind) -> Self {
+        Self::with_error(FaultError::Kind(kind))
+    }
+
+    /// Creates a fault failing every call with the OS error `errno`, such as `ENOSPC`.
+    pub fn os(errno: i32) -> Self {
+        Self::with_error(FaultError::Os(errno))
+    }
+
+    fn with_error(error: FaultError) -> Self {
+        Self {
+            error,
+            operation: None,
+            path: None,
+            thread: None,
+            skip: 0,
+            times: None,
+        }
+    }
+
+    /// Restricts the fault to the calls of `operation`, such as `File::write_all`.
+    pub fn operation(&mut self, operation: &str) -> &mut Self {
+        self.operation = Some(operation.to_string());
+        self
+    }
+
+    /// Restricts the fault to the calls with a path matching the glob `pattern`.
+    ///
+    /// Calls on a [`File`](crate::File) or a [`ReadDir`](crate::ReadDir) match on the path they
+    /// were opened with.
+    ///
+    /// # Panics
+    /// Panics if `pattern` is not a valid glob pattern.
+    pub fn path(&mut self, pattern: &str) -> &mut Self {
+        match glob::Pattern::new(pattern) {
+            Ok(pattern) => self.path = Some(pattern),
+            Err(e) => panic!("invalid glob pattern {:?}: {}", pattern, e),
+        }
+        self
+    }
+
+    /// Restricts the fault to the calls made on the current thread.
+    ///
+    /// This keeps tests running in parallel from seeing each other's faults.
+    pub fn current_thread(&mut self) -> &mut Self {
+        self.thread = Some(thread::current().id());
+        self
+    }
+
+    /// Lets the first `calls` matching calls succeed before failing.
+    pub fn after(&mut self, calls: u64) -> &mut Self {
+        self.skip = calls;
+        self
+    }
+
+    /// Fails at most `calls` matching calls.
+    pub fn times(&mut self, calls: u64) -> &mut Self {
+        self.times = Some(calls);
+        self
+    }
+
+    /// Injects the fault until the returned guard is dropped.
+    pub fn inject(&self) -> FaultGuard {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        faults().push(Injected {
+            id,
+            fault: self.clone(),
+            matched: 0,
+        });
+        ACTIVE.fetch_add(1, Ordering::Relaxed);
+
+        FaultGuard { id }
+    }
+
+    fn matches(&self, operation: &str, accesses: &[(Access, &Path)]) -> bool {
+        if let Some(expected) = &self.operation {
+            if expected != operation {
+                return false;
+            }
+        }
+
+        if let Some(pattern) = &self.path {
+            if !accesses.iter().any(|(_, path)| pattern.matches_path(path)) {
+                return false;
+            }
+        }
+
+        match self.thread {
+            Some(thread) => thread == thread::current().id(),
+            None => true,
+        }
+    }
+}
+
+struct Injected {
+    id: usize,
+    fault: Fault,
+    matched: u64,
+}
+
+/// A guard removing an injected fault when dropped.
+#[derive(Debug)]
+#[must_use = "the fault is removed when the guard is dropped"]
+pub struct FaultGuard {
+    id: usize,
+}
+
+impl Drop for FaultGuard {
+    fn drop(&mut self) {
+        let mut faults = faults();
+        if let Some(index) = faults.iter().position(|injected| injected.id == self.id) {
+            faults.remove(index);
+            ACTIVE.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Returns the error to fail a call of `operation` on `accesses` with, if any fault matches.
+pub(crate) fn check(operation: &str, accesses: &[(Access, &Path)]) -> Option<io::Error> {
+    if ACTIVE.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+
+    let mut faults = faults();
+    for injected in faults.iter_mut() {
+        if !injected.fault.matches(operation, accesses) {
+            continue;
+        }
+
+        injected.matched += 1;
+        let failed = injected.matched.saturating_sub(injected.fault.skip);
+        let exhausted = injected.fault.times.is_some_and(|times| failed > times);
+        if failed == 0 || exhausted {
+            continue;
+        }
+
+        debug!(target: "fs_tracing", operation, fault = ?injected.fault, "injecting fault");
+        return Some(match injected.fault.error {
+            FaultError::Kind(kind) => io::Error::from(kind),
+            FaultError::Os(errno) => io::Error::from_raw_os_error(errno),
+        });
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn after_and_times_count_the_matching_calls() {
+        let _guard = Fault::os(5)
+            .operation("fault::tests::counted")
+            .path("/counted/*")
+            .after(2)
+            .times(3)
+            .inject();
+        let path = Path::new("/counted/file");
+        let other = Path::new("/elsewhere/file");
+
+        let mut failed = Vec::new();
+        for _ in 0..7 {
+            // calls which do not match are not counted.
+            assert!(check("fault::tests::counted", &[(Access::Read, other)]).is_none());
+            assert!(check("fault::tests::other", &[(Access::Read, path)]).is_none());
+            failed.push(
+                check("fault::tests::counted", &[(Access::Read, path)]).map(|e| e.raw_os_error()),
+            );
+        }
+
+        assert_eq!(
+            failed,
+            [
+                None,
+                None,
+                Some(Some(5)),
+                Some(Some(5)),
+                Some(Some(5)),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn dropping_the_guard_removes_the_fault() {
+        let guard = Fault::new(io::ErrorKind::Interrupted)
+            .operation("fault::tests::dropped")
+            .inject();
+        let e = check("fault::tests::dropped", &[]).unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::Interrupted);
+
+        drop(guard);
+        assert!(check("fault::tests::dropped", &[]).is_none());
+    }
+}
@@ -0,0 +1,74 @@
+use crate::audit::Access;
+use std::{fs, io, path::Path};
+use tracing::debug;
+
+/// Like [`remove_file`](crate::remove_file), but succeeds if the file does not exist.
+///
+/// Returns whether the file was removed. A missing file is reported as a `DEBUG` event, and the
+/// other errors are traced in a `remove_file_if_exists` span.
+pub fn remove_file_if_exists<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    fn remove_file_if_exists(path: &Path) -> io::Result<bool> {
+        traced!(
+            "remove_file_if_exists" [Access::Remove => path],
+            match fs::remove_file(path) {
+                Ok(()) => Ok(true),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    debug!(target: "fs_tracing", ?path, "file to remove does not exist");
+                    Ok(false)
+                }
+                Err(e) => Err(e),
+            },
+            ?path
+        )
+    }
+
+    remove_file_if_exists(path.as_ref())
+}
+
+/// Like [`remove_dir_all`](crate::remove_dir_all), but succeeds if the directory does not
+/// exist.
+///
+/// Returns whether the directory was removed. A missing directory is reported as a `DEBUG`
+/// event, and the other errors are traced in a `remove_dir_all_if_exists` span.
+pub fn remove_dir_all_if_exists<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    fn remove_dir_all_if_exists(path: &Path) -> io::Result<bool> {
+        traced!(
+            "remove_dir_all_if_exists" [Access::Remove => path],
+            match fs::remove_dir_all(path) {
+                Ok(()) => Ok(true),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    debug!(target: "fs_tracing", ?path, "directory to remove does not exist");
+                    Ok(false)
+                }
+                Err(e) => Err(e),
+            },
+            ?path
+        )
+    }
+
+    remove_dir_all_if_exists(path.as_ref())
+}
+
+/// Like [`create_dir`](crate::create_dir), but succeeds if the directory already exists.
+///
+/// Returns whether the directory was created. An existing directory is reported as a `DEBUG`
+/// event, and the other errors are traced in a `create_dir_if_missing` span, including an
+/// `AlreadyExists` error if `path` exists but is not a directory.
+pub fn create_dir_if_missing<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    fn create_dir_if_missing(path: &Path) -> io::Result<bool> {
+        traced!(
+            "create_dir_if_missing" [Access::Create => path],
+            match fs::create_dir(path) {
+                Ok(()) => Ok(true),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && path.is_dir() => {
+                    debug!(target: "fs_tracing", ?path, "directory to create already exists");
+                    Ok(false)
+                }
+                Err(e) => Err(e),
+            },
+            ?path
+        )
+    }
+
+    create_dir_if_missing(path.as_ref())
+}
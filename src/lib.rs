@@ -35,6 +35,21 @@
 //!            with path="/not_exist"
 //!              at src/lib.rs:652
 //! ```
+//!
+//! # Features
+//! - `json`: provides [`read_json`] and [`write_json`] for (de)serializing files as JSON with
+//!   [`serde_json`](https://docs.rs/serde_json).
+//! - `metrics`: reports the [statistics](stats) of the wrapped operations, once enabled, to the
+//!   [`metrics`](https://docs.rs/metrics) facade.
+//! - `mmap`: provides [`File::map`] and [`File::map_mut`] for mapping files into memory with
+//!   [`memmap2`](https://docs.rs/memmap2).
+//! - `statvfs`: provides `statvfs` and `available_space` for querying the capacity of a
+//!   filesystem with [`nix`](https://docs.rs/nix), on Unix only.
+//! - `testing`: provides the `testing` module for asserting on the context of the returned
+//!   errors in tests.
+//! - `toml`: provides [`read_toml`] for deserializing files as TOML with
+//!   [`toml`](https://docs.rs/toml).
+//! - `watch`: provides [`Watcher`] for watching filesystem changes with inotify, on Linux only.
 
 // CR pandaman: implement error wrapper
 // CR pandaman: consider whether to #[instrument] non-fallible functions such as builders.
@@ -53,20 +68,79 @@
     missing_debug_implementations,
     missing_docs,
     rust_2018_idioms,
-    rustdoc,
+    rustdoc::all,
     trivial_casts
 )]
 // std does not have ones.
 #![allow(clippy::new_without_default, clippy::len_without_is_empty)]
 
-mod error;
+#[macro_use]
+mod macros;
 
+mod atomic;
+mod copy_dir;
+#[cfg(target_os = "linux")]
+mod dir;
+mod error;
+mod if_exists;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod move_path;
+mod output;
+mod parents;
+mod space;
+mod temp;
+#[cfg(any(feature = "json", feature = "toml"))]
+mod typed;
+#[cfg(all(feature = "watch", target_os = "linux"))]
+mod watch;
+mod wrap;
+
+pub mod audit;
+pub mod build_script;
+pub mod dry_run;
+pub mod fault;
+pub mod policy;
+pub mod replay;
+pub mod retry;
+pub mod slow;
+pub mod stats;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod vfs;
+
+pub use atomic::{write_atomic, AtomicFile, SyncDirError};
+pub use copy_dir::{copy_dir_all, CopyErrors, CopyOptions, Overwrite};
+#[cfg(target_os = "linux")]
+pub use dir::Dir;
+pub use if_exists::{create_dir_if_missing, remove_dir_all_if_exists, remove_file_if_exists};
+#[cfg(all(feature = "mmap", unix))]
+pub use mmap::Advice;
+#[cfg(feature = "mmap")]
+pub use mmap::{Mmap, MmapMut};
+pub use move_path::{move_path, MoveError};
+pub use parents::{copy_with_parents, write_with_parents};
+#[cfg(unix)]
+pub use parents::{copy_with_parents_mode, write_with_parents_mode};
+#[cfg(all(feature = "statvfs", unix))]
+pub use space::{available_space, statvfs, Statvfs};
+pub use space::{disk_usage, DiskUsage, DiskUsageOptions};
+pub use temp::{tempfile, NamedTempFile, TempDir};
+#[cfg(feature = "toml")]
+pub use typed::read_toml;
+#[cfg(feature = "json")]
+pub use typed::{read_json, write_json};
+#[cfg(all(feature = "watch", target_os = "linux"))]
+pub use watch::{WatchEvent, Watcher};
+pub use wrap::{wrap, IoResultExt};
+
+use audit::Access;
 use std::{
     ffi, fmt, fs, io,
     path::{Path, PathBuf},
     process, time,
 };
-use tracing::{debug, instrument};
+use tracing::debug;
 
 /// Wrapper for [`fs::DirBuilder`](std::fs::DirBuilder).
 pub struct DirBuilder {
@@ -79,8 +153,6 @@ impl fmt::Debug for DirBuilder {
     }
 }
 
-// CR pandaman: implement DirBuilderExt for DirBuilder (unix only)
-
 impl DirBuilder {
     /// Wrapper for [`DirBuilder::new`](std::fs::DirBuilder::new).
     pub fn new() -> Self {
@@ -97,16 +169,28 @@ impl DirBuilder {
 
     /// Wrapper for [`DirBuilder::create`](std::fs::DirBuilder::create).
     pub fn create<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
-        // CR pandaman: consult doc for tracing::instrument to mimic the ordinary ordering
-        #[instrument(skip(this), fields(self = ?this, path = ?path))]
         fn create(this: &DirBuilder, path: &Path) -> io::Result<()> {
-            this.inner.create(path).map_err(error::Error::wrap_std)
+            traced!(
+                "DirBuilder::create" [Access::Create => path],
+                this.inner.create(path),
+                self = ?this,
+                ?path
+            )
         }
 
         create(self, path.as_ref())
     }
 }
 
+#[cfg(unix)]
+impl std::os::unix::fs::DirBuilderExt for DirBuilder {
+    /// Wrapper for [`DirBuilderExt::mode`](std::os::unix::fs::DirBuilderExt::mode).
+    fn mode(&mut self, mode: u32) -> &mut Self {
+        std::os::unix::fs::DirBuilderExt::mode(&mut self.inner, mode);
+        self
+    }
+}
+
 /// Wrapper for [`fs::DirEntry`](std::fs::DirEntry).
 pub struct DirEntry {
     inner: fs::DirEntry,
@@ -127,21 +211,21 @@ impl DirEntry {
     }
 
     /// Wrapper for [`DirEntry::metadata`](std::fs::DirEntry::metadata).
-    #[instrument]
     pub fn metadata(&self) -> io::Result<Metadata> {
-        self.inner
-            .metadata()
-            .map(|inner| Metadata { inner })
-            .map_err(error::Error::wrap_std)
+        traced!(
+            "DirEntry::metadata",
+            self.inner.metadata().map(|inner| Metadata { inner }),
+            ?self
+        )
     }
 
     /// Wrapper for [`DirEntry::file_type`](std::fs::DirEntry::file_type).
-    #[instrument]
     pub fn file_type(&self) -> io::Result<FileType> {
-        self.inner
-            .file_type()
-            .map(|inner| FileType { inner })
-            .map_err(error::Error::wrap_std)
+        traced!(
+            "DirEntry::file_type",
+            self.inner.file_type().map(|inner| FileType { inner }),
+            ?self
+        )
     }
 
     /// Wrapper for [`DirEntry::file_name`](std::fs::DirEntry::file_name).
@@ -153,6 +237,7 @@ impl DirEntry {
 /// Wrapper for [`fs::File`](std::fs::File).
 pub struct File {
     inner: fs::File,
+    path: PathBuf,
 }
 
 // CR pandaman: implement extension traits
@@ -169,152 +254,226 @@ impl From<File> for process::Stdio {
     }
 }
 
+// Buffers are recorded by their length only: their contents are rarely useful in an error
+// message and can be arbitrarily large.
+
 impl io::Read for File {
-    #[instrument]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner.read(buf).map_err(error::Error::wrap_std)
+        traced!(
+            "File::read" [Access::Read => &self.path] (buf) => read(|n| *n as u64),
+            self.inner.read(buf),
+            ?self,
+            len = buf.len()
+        )
     }
 
-    #[instrument]
     fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
-        self.inner
-            .read_vectored(bufs)
-            .map_err(error::Error::wrap_std)
+        traced!(
+            "File::read_vectored" [Access::Read => &self.path] (bufs) => read(|n| *n as u64),
+            self.inner.read_vectored(bufs),
+            ?self,
+            bufs = bufs.len()
+        )
     }
 
-    #[instrument]
     fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
-        self.inner.read_to_end(buf).map_err(error::Error::wrap_std)
+        traced!(
+            once "File::read_to_end" [Access::Read => &self.path] (buf) => read(|n| *n as u64),
+            self.inner.read_to_end(buf),
+            ?self
+        )
     }
 
-    #[instrument]
     fn read_to_string(&mut self, buf: &mut String) -> io::Result<usize> {
-        self.inner
-            .read_to_string(buf)
-            .map_err(error::Error::wrap_std)
+        traced!(
+            once "File::read_to_string" [Access::Read => &self.path] (buf) => read(|n| *n as u64),
+            self.inner.read_to_string(buf),
+            ?self
+        )
     }
 
-    #[instrument]
     fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
-        self.inner.read_exact(buf).map_err(error::Error::wrap_std)
+        traced!(
+            once "File::read_exact" [Access::Read => &self.path] (buf) => read(|_| buf.len() as u64),
+            self.inner.read_exact(buf),
+            ?self,
+            len = buf.len()
+        )
     }
 }
 
 impl io::Read for &File {
-    #[instrument]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        (&self.inner).read(buf).map_err(error::Error::wrap_std)
+        traced!(
+            "File::read" [Access::Read => &self.path] (buf) => read(|n| *n as u64),
+            (&self.inner).read(buf),
+            ?self,
+            len = buf.len()
+        )
     }
 
-    #[instrument]
     fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
-        (&self.inner)
-            .read_vectored(bufs)
-            .map_err(error::Error::wrap_std)
+        traced!(
+            "File::read_vectored" [Access::Read => &self.path] (bufs) => read(|n| *n as u64),
+            (&self.inner).read_vectored(bufs),
+            ?self,
+            bufs = bufs.len()
+        )
     }
 
-    #[instrument]
     fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
-        (&self.inner)
-            .read_to_end(buf)
-            .map_err(error::Error::wrap_std)
+        traced!(
+            once "File::read_to_end" [Access::Read => &self.path] (buf) => read(|n| *n as u64),
+            (&self.inner).read_to_end(buf),
+            ?self
+        )
     }
 
-    #[instrument]
     fn read_to_string(&mut self, buf: &mut String) -> io::Result<usize> {
-        (&self.inner)
-            .read_to_string(buf)
-            .map_err(error::Error::wrap_std)
+        traced!(
+            once "File::read_to_string" [Access::Read => &self.path] (buf) => read(|n| *n as u64),
+            (&self.inner).read_to_string(buf),
+            ?self
+        )
     }
 
-    #[instrument]
     fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
-        (&self.inner)
-            .read_exact(buf)
-            .map_err(error::Error::wrap_std)
+        traced!(
+            once "File::read_exact" [Access::Read => &self.path] (buf) => read(|_| buf.len() as u64),
+            (&self.inner).read_exact(buf),
+            ?self,
+            len = buf.len()
+        )
     }
 }
 
 impl io::Seek for File {
-    #[instrument]
     fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
-        self.inner.seek(pos).map_err(error::Error::wrap_std)
+        traced!("File::seek", self.inner.seek(pos), ?self, ?pos)
     }
 }
 
 impl io::Seek for &File {
-    #[instrument]
     fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
-        (&self.inner).seek(pos).map_err(error::Error::wrap_std)
+        traced!("File::seek", (&self.inner).seek(pos), ?self, ?pos)
     }
 }
 
 impl io::Write for File {
-    #[instrument]
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.inner.write(buf).map_err(error::Error::wrap_std)
+        traced!(
+            "File::write" [Access::Write => &self.path] => written(|n| *n as u64)
+                dry_run(buf.len()),
+            self.inner.write(buf),
+            ?self,
+            len = buf.len()
+        )
     }
 
-    #[instrument]
     fn flush(&mut self) -> io::Result<()> {
-        self.inner.flush().map_err(error::Error::wrap_std)
+        traced!("File::flush" [Access::Write => &self.path], self.inner.flush(), ?self)
     }
 
-    #[instrument]
     fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
-        self.inner
-            .write_vectored(bufs)
-            .map_err(error::Error::wrap_std)
+        traced!(
+            "File::write_vectored" [Access::Write => &self.path] => written(|n| *n as u64)
+                dry_run(bufs.iter().map(|buf| buf.len()).sum()),
+            self.inner.write_vectored(bufs),
+            ?self,
+            bufs = bufs.len()
+        )
     }
 
-    #[instrument]
     fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
-        self.inner.write_all(buf).map_err(error::Error::wrap_std)
+        traced!(
+            once "File::write_all" [Access::Write => &self.path] => written(|_| buf.len() as u64),
+            self.inner.write_all(buf),
+            ?self,
+            len = buf.len()
+        )
     }
 
-    #[instrument]
     fn write_fmt(&mut self, fmt: fmt::Arguments<'_>) -> io::Result<()> {
-        self.inner.write_fmt(fmt).map_err(error::Error::wrap_std)
+        traced!(
+            once "File::write_fmt" [Access::Write => &self.path],
+            self.inner.write_fmt(fmt),
+            ?self,
+            ?fmt
+        )
     }
 }
 
 impl io::Write for &File {
-    #[instrument]
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        (&self.inner).write(buf).map_err(error::Error::wrap_std)
+        traced!(
+            "File::write" [Access::Write => &self.path] => written(|n| *n as u64)
+                dry_run(buf.len()),
+            (&self.inner).write(buf),
+            ?self,
+            len = buf.len()
+        )
     }
 
-    #[instrument]
     fn flush(&mut self) -> io::Result<()> {
-        (&self.inner).flush().map_err(error::Error::wrap_std)
+        traced!("File::flush" [Access::Write => &self.path], (&self.inner).flush(), ?self)
     }
 
-    #[instrument]
     fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
-        (&self.inner)
-            .write_vectored(bufs)
-            .map_err(error::Error::wrap_std)
+        traced!(
+            "File::write_vectored" [Access::Write => &self.path] => written(|n| *n as u64)
+                dry_run(bufs.iter().map(|buf| buf.len()).sum()),
+            (&self.inner).write_vectored(bufs),
+            ?self,
+            bufs = bufs.len()
+        )
     }
 
-    #[instrument]
     fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
-        (&self.inner).write_all(buf).map_err(error::Error::wrap_std)
+        traced!(
+            once "File::write_all" [Access::Write => &self.path] => written(|_| buf.len() as u64),
+            (&self.inner).write_all(buf),
+            ?self,
+            len = buf.len()
+        )
     }
 
-    #[instrument]
     fn write_fmt(&mut self, fmt: fmt::Arguments<'_>) -> io::Result<()> {
-        (&self.inner).write_fmt(fmt).map_err(error::Error::wrap_std)
+        traced!(
+            once "File::write_fmt" [Access::Write => &self.path],
+            (&self.inner).write_fmt(fmt),
+            ?self,
+            ?fmt
+        )
     }
 }
 
+#[cfg(unix)]
+const NULL_DEVICE: &str = "/dev/null";
+#[cfg(windows)]
+const NULL_DEVICE: &str = "NUL";
+
 impl File {
+    /// Opens the null device in place of the file at `path`, for the operations which are not
+    /// actually performed.
+    pub(crate) fn null(path: PathBuf) -> io::Result<Self> {
+        let inner = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(NULL_DEVICE)?;
+        Ok(File { inner, path })
+    }
+
     /// Wrapper for [`File::open`](std::fs::File::open).
     pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        #[instrument]
         fn open(path: &Path) -> io::Result<File> {
-            fs::File::open(path)
-                .map(|inner| File { inner })
-                .map_err(error::Error::wrap_std)
+            traced!(
+                "File::open" [Access::Read => path],
+                fs::File::open(path).map(|inner| File {
+                    inner,
+                    path: path.to_path_buf(),
+                }),
+                ?path
+            )
         }
 
         open(path.as_ref())
@@ -322,58 +481,69 @@ impl File {
 
     /// Wrapper for [`File::create`](std::fs::File::create).
     pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        #[instrument]
         fn create(path: &Path) -> io::Result<File> {
-            fs::File::create(path)
-                .map(|inner| File { inner })
-                .map_err(error::Error::wrap_std)
+            traced!(
+                "File::create" [Access::Write => path],
+                fs::File::create(path).map(|inner| File {
+                    inner,
+                    path: path.to_path_buf(),
+                }),
+                ?path
+            )
         }
 
         create(path.as_ref())
     }
 
     /// Wrapper for [`File::sync_all`](std::fs::File::sync_all).
-    #[instrument]
     pub fn sync_all(&self) -> io::Result<()> {
-        self.inner.sync_all().map_err(error::Error::wrap_std)
+        traced!("File::sync_all" [Access::Write => &self.path], self.inner.sync_all(), ?self)
     }
 
     /// Wrapper for [`File::sync_data`](std::fs::File::sync_data).
-    #[instrument]
     pub fn sync_data(&self) -> io::Result<()> {
-        self.inner.sync_data().map_err(error::Error::wrap_std)
+        traced!("File::sync_data" [Access::Write => &self.path], self.inner.sync_data(), ?self)
     }
 
     /// Wrapper for [`File::set_len`](std::fs::File::set_len),
-    #[instrument]
     pub fn set_len(&self, size: u64) -> io::Result<()> {
-        self.inner.set_len(size).map_err(error::Error::wrap_std)
+        traced!(
+            "File::set_len" [Access::Write => &self.path],
+            self.inner.set_len(size),
+            ?self,
+            size
+        )
     }
 
     /// Wrapper for [`File::metadata`](std::fs::File::metadata).
-    #[instrument]
     pub fn metadata(&self) -> io::Result<Metadata> {
-        self.inner
-            .metadata()
-            .map(|inner| Metadata { inner })
-            .map_err(error::Error::wrap_std)
+        traced!(
+            "File::metadata" [Access::Metadata => &self.path],
+            self.inner.metadata().map(|inner| Metadata { inner }),
+            ?self
+        )
     }
 
     /// Wrapper for [`File::try_clone`](std::fs::File::try_clone).
-    #[instrument]
     pub fn try_clone(&self) -> io::Result<File> {
-        self.inner
-            .try_clone()
-            .map(|inner| File { inner })
-            .map_err(error::Error::wrap_std)
+        traced!(
+            "File::try_clone",
+            self.inner.try_clone().map(|inner| File {
+                inner,
+                path: self.path.clone(),
+            }),
+            ?self
+        )
     }
 
     /// Wrapper for [`File::set_permissions`](std::fs::File::set_permissions).
-    #[instrument]
     pub fn set_permissions(&self, perm: Permissions) -> io::Result<()> {
-        self.inner
-            .set_permissions(perm.inner)
-            .map_err(error::Error::wrap_std)
+        traced!(
+            "File::set_permissions" [Access::Write => &self.path],
+            self.inner.set_permissions(perm.inner.clone()),
+            ?self,
+            ?perm
+        )
     }
 }
 
@@ -453,21 +623,18 @@ impl Metadata {
     }
 
     /// Wrapper for [`Metadata::modified`](std::fs::Metadata::modified).
-    #[instrument]
     pub fn modified(&self) -> io::Result<time::SystemTime> {
-        self.inner.modified().map_err(error::Error::wrap_std)
+        traced!("Metadata::modified", self.inner.modified(), ?self)
     }
 
     /// Wrapper for [`Metadata::accessed`](std::fs::Metadata::accessed).
-    #[instrument]
     pub fn accessed(&self) -> io::Result<time::SystemTime> {
-        self.inner.accessed().map_err(error::Error::wrap_std)
+        traced!("Metadata::accessed", self.inner.accessed(), ?self)
     }
 
     /// Wrapper for [`Metadata::created`](std::fs::Metadata::created).
-    #[instrument]
     pub fn created(&self) -> io::Result<time::SystemTime> {
-        self.inner.created().map_err(error::Error::wrap_std)
+        traced!("Metadata::created", self.inner.created(), ?self)
     }
 }
 
@@ -475,6 +642,20 @@ impl Metadata {
 #[derive(Clone)]
 pub struct OpenOptions {
     inner: fs::OpenOptions,
+    // std does not expose the options, so we keep track of the ones determining the access and
+    // the flags used by `Dir`.
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+    #[cfg(unix)]
+    mode: u32,
+    #[cfg(unix)]
+    custom_flags: i32,
+    // `Some` with the mode of the created directories, if any, when creating the parents.
+    parents: Option<Option<u32>>,
 }
 
 impl fmt::Debug for OpenOptions {
@@ -483,60 +664,134 @@ impl fmt::Debug for OpenOptions {
     }
 }
 
-// CR pandaman: implement OpenOptionsExt for OpenOptions
+#[cfg(unix)]
+impl std::os::unix::fs::OpenOptionsExt for OpenOptions {
+    /// Wrapper for [`OpenOptionsExt::mode`](std::os::unix::fs::OpenOptionsExt::mode).
+    fn mode(&mut self, mode: u32) -> &mut Self {
+        std::os::unix::fs::OpenOptionsExt::mode(&mut self.inner, mode);
+        self.mode = mode;
+        self
+    }
+
+    /// Wrapper for
+    /// [`OpenOptionsExt::custom_flags`](std::os::unix::fs::OpenOptionsExt::custom_flags).
+    fn custom_flags(&mut self, flags: i32) -> &mut Self {
+        std::os::unix::fs::OpenOptionsExt::custom_flags(&mut self.inner, flags);
+        self.custom_flags = flags;
+        self
+    }
+}
 
 impl OpenOptions {
     /// Wrapper for [`OpenOptions::new`](std::fs::OpenOptions::new).
     pub fn new() -> Self {
         Self {
             inner: fs::OpenOptions::new(),
+            read: false,
+            write: false,
+            append: false,
+            truncate: false,
+            create: false,
+            create_new: false,
+            #[cfg(unix)]
+            mode: 0o666,
+            #[cfg(unix)]
+            custom_flags: 0,
+            parents: None,
         }
     }
 
     /// Wrapper for [`OpenOptions::read`](std::fs::OpenOptions::read).
     pub fn read(&mut self, read: bool) -> &mut Self {
         self.inner.read(read);
+        self.read = read;
         self
     }
 
     /// Wrapper for [`OpenOptions::write`](std::fs::OpenOptions::write).
     pub fn write(&mut self, write: bool) -> &mut Self {
         self.inner.write(write);
+        self.write = write;
         self
     }
 
     /// Wrapper for [`OpenOptions::append`](std::fs::OpenOptions::append).
     pub fn append(&mut self, append: bool) -> &mut Self {
         self.inner.append(append);
+        self.append = append;
         self
     }
 
     /// Wrapper for [`OpenOptions::truncate`](std::fs::OpenOptions::truncate).
     pub fn truncate(&mut self, truncate: bool) -> &mut Self {
         self.inner.truncate(truncate);
+        self.truncate = truncate;
         self
     }
 
     /// Wrapper for [`OpenOptions::create`](std::fs::OpenOptions::create).
     pub fn create(&mut self, create: bool) -> &mut Self {
         self.inner.create(create);
+        self.create = create;
         self
     }
 
     /// Wrapper for [`OpenOptions::create_new`](std::fs::OpenOptions::create_new).
     pub fn create_new(&mut self, create_new: bool) -> &mut Self {
         self.inner.create_new(create_new);
+        self.create_new = create_new;
+        self
+    }
+
+    /// Creates the missing parent directories of the path before opening it.
+    ///
+    /// A failure to create the directories is traced in a `DirBuilder::create` span inside an
+    /// `OpenOptions::open` span, so that it shows whether creating a directory or opening the file
+    /// failed.
+    pub fn create_parents(&mut self, create_parents: bool) -> &mut Self {
+        self.parents = match (create_parents, self.parents) {
+            (true, Some(mode)) => Some(mode),
+            (true, None) => Some(None),
+            (false, _) => None,
+        };
         self
     }
 
+    /// Sets the mode of the parent directories created with
+    /// [`create_parents`](OpenOptions::create_parents), and enables it.
+    #[cfg(unix)]
+    pub fn parents_mode(&mut self, mode: u32) -> &mut Self {
+        self.parents = Some(Some(mode));
+        self
+    }
+
+    /// Returns the access that opening a path with these options makes.
+    fn access(&self) -> Access {
+        if self.create_new {
+            Access::Create
+        } else if self.write || self.append || self.create {
+            Access::Write
+        } else {
+            Access::Read
+        }
+    }
+
     /// Wrapper for [`OpenOptions::open`](std::fs::OpenOptions::open).
     pub fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
-        #[instrument(skip(this), fields(self = ?this, path = ?path))]
         fn open(this: &OpenOptions, path: &Path) -> io::Result<File> {
-            this.inner
-                .open(path)
-                .map(|inner| File { inner })
-                .map_err(error::Error::wrap_std)
+            if let Some(mode) = this.parents {
+                parents::create("OpenOptions::open", path, mode)?;
+            }
+
+            traced!(
+                "OpenOptions::open" [this.access() => path],
+                this.inner.open(path).map(|inner| File {
+                    inner,
+                    path: path.to_path_buf(),
+                }),
+                self = ?this,
+                ?path
+            )
         }
 
         open(self, path.as_ref())
@@ -572,6 +827,7 @@ impl Permissions {
 /// Wrapper for [`fs::ReadDir`](std::fs::ReadDir).
 pub struct ReadDir {
     inner: fs::ReadDir,
+    path: PathBuf,
     // CR pandaman: consider adding a Span context here
 }
 
@@ -584,21 +840,22 @@ impl fmt::Debug for ReadDir {
 impl Iterator for ReadDir {
     type Item = io::Result<DirEntry>;
 
-    #[instrument]
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|result| {
-            result
-                .map(|inner| DirEntry { inner })
-                .map_err(error::Error::wrap_std)
-        })
+        // the end of the directory is not traced, so that injected faults and replayed events
+        // only apply to the entries.
+        let entry = self.inner.next()?;
+        Some(traced!(
+            once "ReadDir::next" [Access::List => &self.path],
+            entry.map(|inner| DirEntry { inner }),
+            ?self
+        ))
     }
 }
 
 /// Wrapper for [`fs::canonicalize`](std::fs::canonicalize).
 pub fn canonicalize<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
-    #[instrument]
     fn canonicalize(path: &Path) -> io::Result<PathBuf> {
-        fs::canonicalize(path).map_err(error::Error::wrap_std)
+        traced!("canonicalize" [Access::Metadata => path], fs::canonicalize(path), ?path)
     }
 
     canonicalize(path.as_ref())
@@ -606,15 +863,19 @@ pub fn canonicalize<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
 
 /// Wrapper for [`fs::copy`](std::fs::copy).
 pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<u64> {
-    #[instrument]
     fn copy(from: &Path, to: &Path) -> io::Result<u64> {
         // CR pandaman: I don't know why copying between the same file can result in a truncated file
         if from == to {
             // CR pandaman: consider the appropriate log level
-            debug!("`from' and `to' point to the same file");
+            debug!(?from, ?to, "`from' and `to' point to the same file");
         }
 
-        fs::copy(from, to).map_err(error::Error::wrap_std)
+        traced!(
+            "copy" [Access::Read => from, Access::Write => to] => read(|n| *n) => written(|n| *n),
+            fs::copy(from, to),
+            ?from,
+            ?to
+        )
     }
 
     copy(from.as_ref(), to.as_ref())
@@ -622,9 +883,8 @@ pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<u64> {
 
 /// Wrapper for [`fs::create_dir`](std::fs::create_dir).
 pub fn create_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
-    #[instrument]
     fn create_dir(path: &Path) -> io::Result<()> {
-        fs::create_dir(path).map_err(error::Error::wrap_std)
+        traced!("create_dir" [Access::Create => path], fs::create_dir(path), ?path)
     }
 
     create_dir(path.as_ref())
@@ -632,9 +892,8 @@ pub fn create_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
 
 /// Wrapper for [`fs::create_dir_all`](std::fs::create_dir_all).
 pub fn create_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
-    #[instrument]
     fn create_dir_all(path: &Path) -> io::Result<()> {
-        fs::create_dir_all(path).map_err(error::Error::wrap_std)
+        traced!("create_dir_all" [Access::Create => path], fs::create_dir_all(path), ?path)
     }
 
     create_dir_all(path.as_ref())
@@ -642,9 +901,13 @@ pub fn create_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
 
 /// Wrapper for [`fs::hard_link`](std::fs::hard_link).
 pub fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
-    #[instrument]
     fn hard_link(original: &Path, link: &Path) -> io::Result<()> {
-        fs::hard_link(original, link).map_err(error::Error::wrap_std)
+        traced!(
+            "hard_link" [Access::Metadata => original, Access::Create => link],
+            fs::hard_link(original, link),
+            ?original,
+            ?link
+        )
     }
 
     hard_link(original.as_ref(), link.as_ref())
@@ -652,11 +915,12 @@ pub fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Re
 
 /// Wrapper for [`fs::metadata`](std::fs::metadata).
 pub fn metadata<P: AsRef<Path>>(path: P) -> io::Result<Metadata> {
-    #[instrument]
     fn metadata(path: &Path) -> io::Result<Metadata> {
-        fs::metadata(path)
-            .map(|inner| Metadata { inner })
-            .map_err(error::Error::wrap_std)
+        traced!(
+            "metadata" [Access::Metadata => path],
+            fs::metadata(path).map(|inner| Metadata { inner }),
+            ?path
+        )
     }
 
     metadata(path.as_ref())
@@ -664,9 +928,12 @@ pub fn metadata<P: AsRef<Path>>(path: P) -> io::Result<Metadata> {
 
 /// Wrapper for [`fs::read`](std::fs::read).
 pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
-    #[instrument]
     fn read(path: &Path) -> io::Result<Vec<u8>> {
-        fs::read(path).map_err(error::Error::wrap_std)
+        traced!(
+            "read" [Access::Read => path] => read(|data| data.len() as u64),
+            fs::read(path),
+            ?path
+        )
     }
 
     read(path.as_ref())
@@ -674,11 +941,15 @@ pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
 
 /// Wrapper for [`fs::read_dir`](std::fs::read_dir).
 pub fn read_dir<P: AsRef<Path>>(path: P) -> io::Result<ReadDir> {
-    #[instrument]
     fn read_dir(path: &Path) -> io::Result<ReadDir> {
-        fs::read_dir(path)
-            .map(|inner| ReadDir { inner })
-            .map_err(error::Error::wrap_std)
+        traced!(
+            "read_dir" [Access::List => path],
+            fs::read_dir(path).map(|inner| ReadDir {
+                inner,
+                path: path.to_path_buf(),
+            }),
+            ?path
+        )
     }
 
     read_dir(path.as_ref())
@@ -686,9 +957,8 @@ pub fn read_dir<P: AsRef<Path>>(path: P) -> io::Result<ReadDir> {
 
 /// Wrapper for [`fs::read_link`](std::fs::read_link).
 pub fn read_link<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
-    #[instrument]
     fn read_link(path: &Path) -> io::Result<PathBuf> {
-        fs::read_link(path).map_err(error::Error::wrap_std)
+        traced!("read_link" [Access::Read => path], fs::read_link(path), ?path)
     }
 
     read_link(path.as_ref())
@@ -696,9 +966,12 @@ pub fn read_link<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
 
 /// Wrapper for [`fs::read_to_string`](std::fs::read_to_string).
 pub fn read_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
-    #[instrument]
     fn read_to_string(path: &Path) -> io::Result<String> {
-        fs::read_to_string(path).map_err(error::Error::wrap_std)
+        traced!(
+            "read_to_string" [Access::Read => path] => read(|data| data.len() as u64),
+            fs::read_to_string(path),
+            ?path
+        )
     }
 
     read_to_string(path.as_ref())
@@ -706,9 +979,8 @@ pub fn read_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
 
 /// Wrapper for [`fs::remove_dir`](std::fs::remove_dir).
 pub fn remove_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
-    #[instrument]
     fn remove_dir(path: &Path) -> io::Result<()> {
-        fs::remove_dir(path).map_err(error::Error::wrap_std)
+        traced!("remove_dir" [Access::Remove => path], fs::remove_dir(path), ?path)
     }
 
     remove_dir(path.as_ref())
@@ -716,9 +988,8 @@ pub fn remove_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
 
 /// Wrapper for [`fs::remove_dir_all`](std::fs::remove_dir_all).
 pub fn remove_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
-    #[instrument]
     fn remove_dir_all(path: &Path) -> io::Result<()> {
-        fs::remove_dir_all(path).map_err(error::Error::wrap_std)
+        traced!("remove_dir_all" [Access::Remove => path], fs::remove_dir_all(path), ?path)
     }
 
     remove_dir_all(path.as_ref())
@@ -726,9 +997,8 @@ pub fn remove_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
 
 /// Wrapper for [`fs::remove_file`](std::fs::remove_file).
 pub fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
-    #[instrument]
     fn remove_file(path: &Path) -> io::Result<()> {
-        fs::remove_file(path).map_err(error::Error::wrap_std)
+        traced!("remove_file" [Access::Remove => path], fs::remove_file(path), ?path)
     }
 
     remove_file(path.as_ref())
@@ -736,9 +1006,13 @@ pub fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
 
 /// Wrapper for [`fs::rename`](std::fs::rename).
 pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
-    #[instrument]
     fn rename(from: &Path, to: &Path) -> io::Result<()> {
-        fs::rename(from, to).map_err(error::Error::wrap_std)
+        traced!(
+            "rename" [Access::Remove => from, Access::Write => to],
+            fs::rename(from, to),
+            ?from,
+            ?to
+        )
     }
 
     rename(from.as_ref(), to.as_ref())
@@ -746,9 +1020,13 @@ pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()>
 
 /// Wrapper for [`fs::set_permissions`](std::fs::set_permissions).
 pub fn set_permissions<P: AsRef<Path>>(path: P, perm: Permissions) -> io::Result<()> {
-    #[instrument]
     fn set_permissions(path: &Path, perm: Permissions) -> io::Result<()> {
-        fs::set_permissions(path, perm.inner).map_err(error::Error::wrap_std)
+        traced!(
+            "set_permissions" [Access::Write => path],
+            fs::set_permissions(path, perm.inner.clone()),
+            ?path,
+            ?perm
+        )
     }
 
     set_permissions(path.as_ref(), perm)
@@ -756,11 +1034,12 @@ pub fn set_permissions<P: AsRef<Path>>(path: P, perm: Permissions) -> io::Result
 
 /// Wrapper for [`fs::symlink_metadata`](std::fs::symlink_metadata).
 pub fn symlink_metadata<P: AsRef<Path>>(path: P) -> io::Result<Metadata> {
-    #[instrument]
     fn symlink_metadata(path: &Path) -> io::Result<Metadata> {
-        fs::symlink_metadata(path)
-            .map(|inner| Metadata { inner })
-            .map_err(error::Error::wrap_std)
+        traced!(
+            "symlink_metadata" [Access::Metadata => path],
+            fs::symlink_metadata(path).map(|inner| Metadata { inner }),
+            ?path
+        )
     }
 
     symlink_metadata(path.as_ref())
@@ -768,9 +1047,13 @@ pub fn symlink_metadata<P: AsRef<Path>>(path: P) -> io::Result<Metadata> {
 
 /// Wrapper for [`fs::write`](std::fs::write).
 pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
-    #[instrument]
     fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
-        fs::write(path, contents).map_err(error::Error::wrap_std)
+        traced!(
+            "write" [Access::Write => path] => written(|_| contents.len() as u64),
+            fs::write(path, contents),
+            ?path,
+            len = contents.len()
+        )
     }
 
     write(path.as_ref(), contents.as_ref())
@@ -0,0 +1,174 @@
+/// Evaluates `$call` and, only if it fails, enters a span named `$name` with the given fields
+/// before wrapping the error, so that the successful path never pays for creating a span.
+///
+/// The optional bracketed list declares the paths accessed by the call as
+/// `Access::Kind => path` pairs, which are reported to [`audit`](crate::audit) before the call and
+/// checked against the [policy](crate::policy) and the injected [faults](crate::fault), which
+/// replace the call with an error.
+///
+/// Each call is recorded in [`stats`](crate::stats) under `$name`, and reported as a `WARN` event
+/// with the same fields if it is [slow](crate::slow). The call is only timed if either of them is
+/// enabled. The optional `=> read(...)` and `=> written(...)` clauses take closures computing the
+/// number of bytes transferred from a reference to the successful result.
+///
+/// Mutating calls are skipped in [dry-run mode](crate::dry_run), returning the value of the
+/// optional `dry_run(...)` clause if given, such as the length of the buffer of `File::write`. Each
+/// call is also [recorded or replayed](crate::replay). The optional parenthesized expression is the buffer
+/// filled by a read, such as the argument of `File::read`, whose contents are recorded and
+/// replayed along with the result.
+///
+/// A call failing with an error the [retry policy](crate::retry) considers transient is attempted
+/// again, so `$call` is evaluated once per attempt. The span of a call failing after several
+/// attempts records their number in its `attempts` field. A call which may have partially taken
+/// effect before failing is declared with a leading `once`, so that it is attempted only once and
+/// `$call` may move values.
+///
+/// A call blocking until something happens, rather than until the filesystem completes it, is
+/// declared with a leading `untimed`, so that waiting is neither recorded in the latency
+/// histogram nor reported as slow, while the call and its error are still counted.
+///
+/// The optional `within(...)` clause takes a closure returning a span to create the span of a
+/// failed call in, for the calls made on behalf of another operation. It is only called on error.
+///
+/// With a leading `span_only`, the call is only wrapped in the span on error, without any of the
+/// process-wide hooks above, for the operations which do not touch the real filesystem.
+///
+/// The span and the event always have the `fs_tracing` target, so that an operation is reported the
+/// same way regardless of the module implementing it. The fields use the same syntax as
+/// [`tracing::span!`].
+macro_rules! traced {
+    (@buffer) => { None::<&mut [u8]> };
+    (@buffer $buffer:expr) => { Some(&mut *$buffer) };
+    (@mark) => { 0 };
+    (@mark $buffer:expr) => { crate::replay::Buffer::mark(&*$buffer) };
+    (@filled $mark:ident) => {{
+        let _ = $mark;
+        None::<(&[u8], usize)>
+    }};
+    (@filled $mark:ident $buffer:expr) => { Some((&*$buffer, $mark)) };
+    (@within) => { tracing::Span::none() };
+    (@within $parent:expr) => { ($parent)() };
+    (@skip $accesses:ident) => { crate::dry_run::skip($accesses) };
+    (@skip $accesses:ident $skipped:expr) => { crate::dry_run::skip_with($accesses, || $skipped) };
+    (@timed timed) => { crate::stats::is_enabled() || crate::slow::is_enabled() };
+    (@timed untimed) => { false };
+    (@attempts once $name:literal, $attempt:block, ($($field:tt)*)) => {{
+        let (result, elapsed) = $attempt;
+        (result, elapsed, 1u32)
+    }};
+    (@attempts retry $name:literal, $attempt:block, ($($field:tt)*)) => {{
+        let mut attempts: u32 = 1;
+        loop {
+            let (result, elapsed) = $attempt;
+            if let Err(error) = &result {
+                if let Some(delay) = crate::retry::delay(error, attempts) {
+                    tracing::warn!(
+                        target: "fs_tracing",
+                        operation = $name,
+                        attempt = attempts,
+                        ?delay,
+                        %error
+                        $($field)*,
+                        "retrying transient error"
+                    );
+                    std::thread::sleep(delay);
+                    attempts += 1;
+                    continue;
+                }
+            }
+            break (result, elapsed, attempts);
+        }
+    }};
+    (
+        @traced $retry:ident $timing:ident
+        $name:literal
+        $([$($access:expr => $path:expr),* $(,)?])?
+        $(($buffer:expr))?
+        $(=> $direction:ident($bytes:expr))*
+        $(dry_run($skipped:expr))?
+        $(within($parent:expr))?,
+        $call:expr
+        $(, $($field:tt)*)?
+    ) => {{
+        static STATS: crate::stats::Callsite = crate::stats::Callsite::new($name);
+
+        let accesses: &[(crate::audit::Access, &std::path::Path)] =
+            &[$($(($access, $path)),*)?];
+        crate::audit::access(accesses);
+
+        let timed = traced!(@timed $timing);
+        let (result, elapsed, attempts) = traced!(@attempts $retry $name, {
+            let mark = traced!(@mark $($buffer)?);
+            let start = if timed { Some(std::time::Instant::now()) } else { None };
+            let replayed = match crate::policy::check($name, accesses)
+                .or_else(|| crate::fault::check($name, accesses))
+            {
+                Some(error) => Some(Err(error)),
+                None => traced!(@skip accesses $($skipped)?)
+                    .map(|result| {
+                        tracing::info!(
+                            target: "fs_tracing",
+                            operation = $name
+                            $(, $($field)*)?,
+                            "skipped mutating operation in dry run"
+                        );
+                        result
+                    })
+                    .or_else(|| {
+                        crate::replay::take($name, accesses).map(|event| {
+                            crate::replay::result(event, traced!(@buffer $($buffer)?))
+                        })
+                    }),
+            };
+            let result = match replayed {
+                Some(result) => result,
+                None => $call,
+            };
+            let elapsed = start.map(|start| start.elapsed());
+            crate::replay::record($name, accesses, &result, traced!(@filled mark $($buffer)?));
+            (result, elapsed)
+        }, ($(, $($field)*)?));
+
+        STATS.record(elapsed, result.as_ref().err().map(|e| e.kind()));
+        if let Some(elapsed) = elapsed {
+            if let Some(threshold) = crate::slow::exceeded($name, elapsed) {
+                tracing::warn!(
+                    target: "fs_tracing",
+                    operation = $name,
+                    ?elapsed,
+                    ?threshold
+                    $(, $($field)*)?,
+                    "slow filesystem operation"
+                );
+            }
+        }
+
+        match result {
+            Ok(value) => {
+                $(STATS.$direction(&value, $bytes);)*
+                Ok(value)
+            }
+            Err(error) => traced!(@within $($parent)?).in_scope(|| {
+                let span = tracing::info_span!(
+                    target: "fs_tracing",
+                    $name,
+                    attempts = tracing::field::Empty
+                    $(, $($field)*)?
+                );
+                if attempts > 1 {
+                    span.record("attempts", attempts);
+                }
+                Err(span.in_scope(|| crate::error::Error::wrap_std(error)))
+            }),
+        }
+    }};
+    (span_only $name:literal, $call:expr $(, $($field:tt)*)?) => {
+        ($call).map_err(|error| {
+            tracing::info_span!(target: "fs_tracing", $name $(, $($field)*)?)
+                .in_scope(|| crate::error::Error::wrap_std(error))
+        })
+    };
+    (once $name:literal $($rest:tt)*) => { traced!(@traced once timed $name $($rest)*) };
+    (untimed $name:literal $($rest:tt)*) => { traced!(@traced retry untimed $name $($rest)*) };
+    ($name:literal $($rest:tt)*) => { traced!(@traced retry timed $name $($rest)*) };
+}
@@ -0,0 +1,260 @@
+use crate::{audit::Access, File};
+use std::{
+    fmt, io,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+pub use memmap2::Advice;
+
+fn options(offset: u64, len: Option<usize>) -> memmap2::MmapOptions {
+    let mut options = memmap2::MmapOptions::new();
+    options.offset(offset);
+    if let Some(len) = len {
+        options.len(len);
+    }
+    options
+}
+
+// Mapping a file is unsafe because the mapped memory changes if the file is modified by another
+// process, so the mapping functions are unsafe like their counterparts in memmap2.
+#[allow(unsafe_code)]
+impl File {
+    /// Maps the whole file into memory for reading.
+    ///
+    /// Wrapper for [`memmap2::Mmap::map`]. The file must be opened for reading.
+    ///
+    /// # Safety
+    /// The behavior is undefined if the mapped file is modified or truncated while the mapping
+    /// is alive, by this process or another one. See [`memmap2::Mmap`] for details.
+    pub unsafe fn map(&self) -> io::Result<Mmap> {
+        self.map_with(0, None)
+    }
+
+    /// Maps `len` bytes of the file starting at `offset` into memory for reading.
+    ///
+    /// # Safety
+    /// See [`File::map`].
+    pub unsafe fn map_range(&self, offset: u64, len: usize) -> io::Result<Mmap> {
+        self.map_with(offset, Some(len))
+    }
+
+    unsafe fn map_with(&self, offset: u64, len: Option<usize>) -> io::Result<Mmap> {
+        traced!(
+            "File::map" [Access::Read => &self.path],
+            unsafe { options(offset, len).map(&self.inner) }.map(|inner| Mmap {
+                inner,
+                path: self.path.clone(),
+                offset,
+            }),
+            path = ?self.path,
+            offset,
+            len = ?len
+        )
+    }
+
+    /// Maps the whole file into memory for reading and writing.
+    ///
+    /// Wrapper for [`memmap2::MmapMut::map_mut`]. The file must be opened for reading and
+    /// writing. In [dry-run mode](crate::dry_run), the mapping is private, so that the writes to
+    /// it never reach the file.
+    ///
+    /// # Safety
+    /// The behavior is undefined if the mapped file is modified or truncated while the mapping
+    /// is alive, by this process or another one. See [`memmap2::MmapMut`] for details.
+    pub unsafe fn map_mut(&self) -> io::Result<MmapMut> {
+        self.map_mut_with(0, None)
+    }
+
+    /// Maps `len` bytes of the file starting at `offset` into memory for reading and writing.
+    ///
+    /// # Safety
+    /// See [`File::map_mut`].
+    pub unsafe fn map_mut_range(&self, offset: u64, len: usize) -> io::Result<MmapMut> {
+        self.map_mut_with(offset, Some(len))
+    }
+
+    unsafe fn map_mut_with(&self, offset: u64, len: Option<usize>) -> io::Result<MmapMut> {
+        traced!(
+            "File::map_mut" [Access::Write => &self.path],
+            if crate::dry_run::is_enabled() {
+                unsafe { options(offset, len).map_copy(&self.inner) }
+            } else {
+                unsafe { options(offset, len).map_mut(&self.inner) }
+            }
+            .map(|inner| MmapMut {
+                inner,
+                path: self.path.clone(),
+                offset,
+            }),
+            path = ?self.path,
+            offset,
+            len = ?len
+        )
+    }
+}
+
+/// A read-only memory map of a file, created by [`File::map`].
+///
+/// It dereferences to the mapped bytes.
+pub struct Mmap {
+    inner: memmap2::Mmap,
+    path: PathBuf,
+    offset: u64,
+}
+
+impl fmt::Debug for Mmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mmap")
+            .field("path", &self.path)
+            .field("offset", &self.offset)
+            .field("len", &self.inner.len())
+            .finish()
+    }
+}
+
+impl Mmap {
+    /// Returns the path of the mapped file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the offset in the file where the mapping starts.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Wrapper for [`memmap2::Mmap::advise`].
+    #[cfg(unix)]
+    pub fn advise(&self, advice: Advice) -> io::Result<()> {
+        traced!(
+            "Mmap::advise",
+            self.inner.advise(advice),
+            path = ?self.path,
+            offset = self.offset,
+            len = self.inner.len(),
+            ?advice
+        )
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.inner
+    }
+}
+
+impl AsRef<[u8]> for Mmap {
+    fn as_ref(&self) -> &[u8] {
+        &self.inner
+    }
+}
+
+/// A writable memory map of a file, created by [`File::map_mut`].
+///
+/// It dereferences to the mapped bytes. The changes are written back to the file eventually, or
+/// when [flushed](MmapMut::flush).
+pub struct MmapMut {
+    inner: memmap2::MmapMut,
+    path: PathBuf,
+    offset: u64,
+}
+
+impl fmt::Debug for MmapMut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MmapMut")
+            .field("path", &self.path)
+            .field("offset", &self.offset)
+            .field("len", &self.inner.len())
+            .finish()
+    }
+}
+
+impl MmapMut {
+    /// Returns the path of the mapped file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the offset in the file where the mapping starts.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Wrapper for [`memmap2::MmapMut::flush`].
+    pub fn flush(&self) -> io::Result<()> {
+        traced!(
+            "MmapMut::flush" [Access::Write => &self.path],
+            self.inner.flush(),
+            path = ?self.path,
+            offset = self.offset,
+            len = self.inner.len()
+        )
+    }
+
+    /// Wrapper for [`memmap2::MmapMut::flush_async`].
+    pub fn flush_async(&self) -> io::Result<()> {
+        traced!(
+            "MmapMut::flush_async" [Access::Write => &self.path],
+            self.inner.flush_async(),
+            path = ?self.path,
+            offset = self.offset,
+            len = self.inner.len()
+        )
+    }
+
+    /// Wrapper for [`memmap2::MmapMut::flush_range`].
+    ///
+    /// `offset` is relative to the start of the mapping.
+    pub fn flush_range(&self, offset: usize, len: usize) -> io::Result<()> {
+        traced!(
+            "MmapMut::flush_range" [Access::Write => &self.path],
+            self.inner.flush_range(offset, len),
+            path = ?self.path,
+            offset = self.offset + offset as u64,
+            len
+        )
+    }
+
+    /// Wrapper for [`memmap2::MmapMut::advise`].
+    #[cfg(unix)]
+    pub fn advise(&self, advice: Advice) -> io::Result<()> {
+        traced!(
+            "MmapMut::advise",
+            self.inner.advise(advice),
+            path = ?self.path,
+            offset = self.offset,
+            len = self.inner.len(),
+            ?advice
+        )
+    }
+}
+
+impl Deref for MmapMut {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.inner
+    }
+}
+
+impl DerefMut for MmapMut {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.inner
+    }
+}
+
+impl AsRef<[u8]> for MmapMut {
+    fn as_ref(&self) -> &[u8] {
+        &self.inner
+    }
+}
+
+impl AsMut<[u8]> for MmapMut {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.inner
+    }
+}
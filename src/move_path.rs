@@ -0,0 +1,185 @@
+use crate::{
+    audit::Access,
+    copy_dir::{self, CopyOptions, Progress},
+};
+use std::{
+    error, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+use tracing::info_span;
+
+/// The failure of the fallback of [`move_path`], after the rename failed across devices.
+///
+/// It is returned inside an `io::Error` with the kind of the underlying failure, and can be
+/// retrieved with [`io::Error::get_ref`] and `downcast_ref`.
+#[derive(Debug)]
+pub struct MoveError {
+    to: PathBuf,
+    copied: bool,
+    bytes: u64,
+    entries: Vec<PathBuf>,
+    source: io::Error,
+}
+
+impl MoveError {
+    /// Returns whether the source was completely copied to the destination before the failure,
+    /// in which case only its removal failed.
+    pub fn copied(&self) -> bool {
+        self.copied
+    }
+
+    /// Returns the number of bytes copied to the destination before the failure.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Returns the entries of the source directory which were completely copied to the
+    /// destination before the failure, relative to the source. A directory is listed once all
+    /// its contents are. This is empty when moving a file.
+    pub fn entries(&self) -> &[PathBuf] {
+        &self.entries
+    }
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.copied {
+            writeln!(
+                f,
+                "the source was copied to {} but could not be removed",
+                self.to.display()
+            )?;
+        } else {
+            writeln!(
+                f,
+                "{} entries and {} bytes of the source were copied to {}, and the source is left \
+                 untouched",
+                self.entries.len(),
+                self.bytes,
+                self.to.display()
+            )?;
+        }
+        write!(f, "{}", self.source)
+    }
+}
+
+impl error::Error for MoveError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Moves a file or a directory, even across devices.
+///
+/// `move_path` first tries [`rename`](crate::rename). If it fails because `from` and `to` are on
+/// different devices, `move_path` copies `from` to `to` with its permissions and timestamps, then
+/// removes `from`. The rename is traced with `strategy = "rename"`, and the steps of the fallback
+/// run in a `move_path` span with `strategy = "copy"`, so that their errors show which strategy
+/// ran. A failure of the fallback is reported with a [`MoveError`] telling what was copied.
+///
+/// Like the rename, the fallback replaces an existing file at `to` when moving a file, and an
+/// existing empty directory when moving a directory. It fails if `to` is a non-empty directory,
+/// or if one of `from` and `to` is a directory and the other is not.
+pub fn move_path<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+        match traced!(
+            "move_path" [Access::Remove => from, Access::Write => to],
+            fs::rename(from, to),
+            strategy = "rename",
+            ?from,
+            ?to
+        ) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+            result => return result,
+        }
+
+        let span = info_span!(target: "fs_tracing", "move_path", strategy = "copy", ?from, ?to);
+        let _enter = span.enter();
+
+        let fail = |copied, progress: Progress, source: io::Error| {
+            io::Error::new(
+                source.kind(),
+                MoveError {
+                    to: to.to_path_buf(),
+                    copied,
+                    bytes: progress.bytes,
+                    entries: progress.entries,
+                    source,
+                },
+            )
+        };
+
+        let metadata = crate::symlink_metadata(from)?;
+        traced!(
+            "move_path" [Access::Remove => to],
+            prepare(to, metadata.inner.file_type()),
+            step = "prepare",
+            ?from,
+            ?to
+        )
+        .map_err(|e| fail(false, Progress::default(), e))?;
+
+        let mut progress = Progress::default();
+        if metadata.is_dir() {
+            let mut options = CopyOptions::new();
+            options.preserve_permissions(true).preserve_timestamps(true);
+            if let Err(e) = copy_dir::copy_tree(from, to, &options, &mut progress, true) {
+                return Err(fail(false, progress, e));
+            }
+            crate::remove_dir_all(from).map_err(|e| fail(true, progress, e))
+        } else {
+            if let Err(e) = copy(from, to, &metadata.inner) {
+                return Err(fail(false, progress, e));
+            }
+            progress.bytes = metadata.len();
+            crate::remove_file(from).map_err(|e| fail(true, progress, e))
+        }
+    }
+
+    move_path(from.as_ref(), to.as_ref())
+}
+
+/// Removes what the rename would replace at `to` but copying would not, and fails where the rename
+/// would.
+fn prepare(to: &Path, from: fs::FileType) -> io::Result<()> {
+    let existing = match fs::symlink_metadata(to) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    match (from.is_dir(), existing.is_dir()) {
+        // a non-empty directory fails to be removed, as it fails to be replaced.
+        (true, true) => fs::remove_dir(to),
+        (true, false) => Err(io::Error::from(io::ErrorKind::NotADirectory)),
+        (false, true) => Err(io::Error::from(io::ErrorKind::IsADirectory)),
+        // a link cannot be created over an existing entry, and a file would be copied through an
+        // existing link, while a regular file is overwritten in place.
+        (false, false) if from.is_symlink() || existing.file_type().is_symlink() => {
+            fs::remove_file(to)
+        }
+        (false, false) => Ok(()),
+    }
+}
+
+/// Copies a file or a symbolic link with its timestamps.
+fn copy(from: &Path, to: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    if metadata.file_type().is_symlink() {
+        return traced!(
+            "move_path" [Access::Read => from, Access::Create => to],
+            fs::read_link(from).and_then(|target| copy_dir::symlink(&target, to)),
+            step = "symlink",
+            ?from,
+            ?to
+        );
+    }
+
+    crate::copy(from, to)?;
+    traced!(
+        "move_path" [Access::Write => to],
+        copy_dir::set_times(to, metadata),
+        step = "set_times",
+        ?from,
+        ?to
+    )
+}
@@ -0,0 +1,182 @@
+use crate::{
+    audit::Access,
+    replay::{self, Value},
+    File,
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time,
+};
+
+/// The result types of the wrappers, which tell how their results are recorded, replayed, and
+/// made up in dry-run mode.
+///
+/// Every type returned through `traced!` implements it, and the defaults fit the results which
+/// can be neither reconstructed nor made up.
+pub(crate) trait Output: Sized {
+    /// Summarizes `self` for a trace, returning the data read if `self` consists of them.
+    fn record(&self) -> (Value, Option<&[u8]>) {
+        (Value::Opaque, None)
+    }
+
+    /// Reconstructs a result from its recorded summary.
+    fn replay(_value: &Value, _data: Option<&[u8]>, _paths: &[PathBuf]) -> io::Result<Self> {
+        Err(replay::diverged(
+            "the recorded result cannot be reconstructed",
+        ))
+    }
+
+    /// Returns the result of a mutating call on `accesses` skipped in dry-run mode, or `None` if
+    /// the call must be performed because it is harmless.
+    fn skipped(_accesses: &[(Access, &Path)]) -> Option<io::Result<Self>> {
+        None
+    }
+}
+
+impl Output for () {
+    fn record(&self) -> (Value, Option<&[u8]>) {
+        (Value::Unit, None)
+    }
+
+    fn replay(value: &Value, _data: Option<&[u8]>, _paths: &[PathBuf]) -> io::Result<Self> {
+        match value {
+            Value::Unit => Ok(()),
+            _ => Err(replay::mismatched()),
+        }
+    }
+
+    fn skipped(_accesses: &[(Access, &Path)]) -> Option<io::Result<Self>> {
+        Some(Ok(()))
+    }
+}
+
+impl Output for u64 {
+    fn record(&self) -> (Value, Option<&[u8]>) {
+        (Value::Count(*self), None)
+    }
+
+    fn replay(value: &Value, _data: Option<&[u8]>, _paths: &[PathBuf]) -> io::Result<Self> {
+        match value {
+            Value::Count(count) => Ok(*count),
+            _ => Err(replay::mismatched()),
+        }
+    }
+
+    fn skipped(accesses: &[(Access, &Path)]) -> Option<io::Result<Self>> {
+        // the number of bytes copied.
+        let source = accesses.iter().find(|(access, _)| *access == Access::Read);
+        Some(Ok(source.map_or(0, |(_, path)| {
+            std::fs::metadata(path).map_or(0, |metadata| metadata.len())
+        })))
+    }
+}
+
+impl Output for usize {
+    fn record(&self) -> (Value, Option<&[u8]>) {
+        (Value::Count(*self as u64), None)
+    }
+
+    fn replay(value: &Value, _data: Option<&[u8]>, _paths: &[PathBuf]) -> io::Result<Self> {
+        match value {
+            Value::Count(count) => Ok(*count as usize),
+            _ => Err(replay::mismatched()),
+        }
+    }
+}
+
+impl Output for bool {
+    fn record(&self) -> (Value, Option<&[u8]>) {
+        (Value::Count(u64::from(*self)), None)
+    }
+
+    fn replay(value: &Value, _data: Option<&[u8]>, _paths: &[PathBuf]) -> io::Result<Self> {
+        match value {
+            Value::Count(count) => Ok(*count != 0),
+            _ => Err(replay::mismatched()),
+        }
+    }
+
+    fn skipped(accesses: &[(Access, &Path)]) -> Option<io::Result<Self>> {
+        // whether something would have been created or removed.
+        let (access, path) = accesses.first()?;
+        let exists = path.symlink_metadata().is_ok();
+        Some(Ok(if *access == Access::Create {
+            !exists
+        } else {
+            exists
+        }))
+    }
+}
+
+impl Output for Vec<u8> {
+    fn record(&self) -> (Value, Option<&[u8]>) {
+        (Value::Bytes, Some(self))
+    }
+
+    fn replay(value: &Value, data: Option<&[u8]>, _paths: &[PathBuf]) -> io::Result<Self> {
+        match value {
+            Value::Bytes => data.map(<[u8]>::to_vec).ok_or_else(replay::unrecorded),
+            _ => Err(replay::mismatched()),
+        }
+    }
+}
+
+impl Output for String {
+    fn record(&self) -> (Value, Option<&[u8]>) {
+        (Value::Bytes, Some(self.as_bytes()))
+    }
+
+    fn replay(value: &Value, data: Option<&[u8]>, _paths: &[PathBuf]) -> io::Result<Self> {
+        match value {
+            Value::Bytes => String::from_utf8(data.ok_or_else(replay::unrecorded)?.to_vec())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            _ => Err(replay::mismatched()),
+        }
+    }
+}
+
+impl Output for PathBuf {
+    fn record(&self) -> (Value, Option<&[u8]>) {
+        (Value::Path(self.clone()), None)
+    }
+
+    fn replay(value: &Value, _data: Option<&[u8]>, _paths: &[PathBuf]) -> io::Result<Self> {
+        match value {
+            Value::Path(path) => Ok(path.clone()),
+            _ => Err(replay::mismatched()),
+        }
+    }
+}
+
+impl Output for File {
+    fn replay(_value: &Value, _data: Option<&[u8]>, paths: &[PathBuf]) -> io::Result<Self> {
+        File::null(paths.first().cloned().unwrap_or_default())
+    }
+
+    fn skipped(accesses: &[(Access, &Path)]) -> Option<io::Result<Self>> {
+        // failing to open the null device fails the call rather than performing it.
+        let (_, path) = accesses.first()?;
+        Some(File::null(path.to_path_buf()))
+    }
+}
+
+impl Output for time::SystemTime {}
+impl Output for Vec<PathBuf> {}
+impl Output for crate::Metadata {}
+impl Output for crate::FileType {}
+impl Output for crate::ReadDir {}
+impl Output for crate::DirEntry {}
+#[cfg(target_os = "linux")]
+impl Output for crate::Dir {}
+#[cfg(all(feature = "statvfs", unix))]
+impl Output for crate::Statvfs {}
+// Mapping for writing is performed in dry-run mode, but with a private mapping.
+#[cfg(feature = "mmap")]
+impl Output for crate::Mmap {}
+#[cfg(feature = "mmap")]
+impl Output for crate::MmapMut {}
+#[cfg(all(feature = "watch", target_os = "linux"))]
+impl Output for crate::Watcher {}
+#[cfg(all(feature = "watch", target_os = "linux"))]
+impl Output for Vec<crate::WatchEvent> {}
@@ -0,0 +1,115 @@
+use crate::{audit::Access, File};
+use std::{fs, io, panic::Location, path::Path};
+
+/// Creates the missing ancestors of `path`, with `mode` on Unix if given.
+///
+/// A failure is traced in a `DirBuilder::create` span with the parent and `create_parents = true`,
+/// created inside a span of `operation` with `path`, so that the trace reads as
+/// `write > DirBuilder::create` and tells creating a directory from performing the operation apart.
+#[track_caller]
+pub(crate) fn create(operation: &'static str, path: &Path, mode: Option<u32>) -> io::Result<()> {
+    let location = Location::caller();
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => return Ok(()),
+    };
+
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    traced!(
+        "DirBuilder::create" [Access::Create => parent]
+            within(|| crate::wrap::span(operation, &[("path", &path)], location)),
+        builder.create(parent),
+        path = ?parent,
+        create_parents = true
+    )
+}
+
+/// Like [`write`](crate::write), but creates the missing parent directories of `path` first.
+///
+/// A failure to create the directories is traced in a `DirBuilder::create` span inside a `write`
+/// span, so that it shows whether creating a directory or writing the file failed.
+pub fn write_with_parents<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    fn write_with_parents(path: &Path, contents: &[u8]) -> io::Result<()> {
+        create("write", path, None)?;
+        crate::write(path, contents)
+    }
+
+    write_with_parents(path.as_ref(), contents.as_ref())
+}
+
+/// Like [`write_with_parents`], but creates the directories with `mode`.
+#[cfg(unix)]
+pub fn write_with_parents_mode<P: AsRef<Path>, C: AsRef<[u8]>>(
+    path: P,
+    contents: C,
+    mode: u32,
+) -> io::Result<()> {
+    fn write_with_parents_mode(path: &Path, contents: &[u8], mode: u32) -> io::Result<()> {
+        create("write", path, Some(mode))?;
+        crate::write(path, contents)
+    }
+
+    write_with_parents_mode(path.as_ref(), contents.as_ref(), mode)
+}
+
+/// Like [`copy`](crate::copy), but creates the missing parent directories of `to` first.
+///
+/// A failure to create the directories is traced in a `DirBuilder::create` span inside a `copy`
+/// span.
+pub fn copy_with_parents<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<u64> {
+    fn copy_with_parents(from: &Path, to: &Path) -> io::Result<u64> {
+        create("copy", to, None)?;
+        crate::copy(from, to)
+    }
+
+    copy_with_parents(from.as_ref(), to.as_ref())
+}
+
+/// Like [`copy_with_parents`], but creates the directories with `mode`.
+#[cfg(unix)]
+pub fn copy_with_parents_mode<P: AsRef<Path>, Q: AsRef<Path>>(
+    from: P,
+    to: Q,
+    mode: u32,
+) -> io::Result<u64> {
+    fn copy_with_parents_mode(from: &Path, to: &Path, mode: u32) -> io::Result<u64> {
+        create("copy", to, Some(mode))?;
+        crate::copy(from, to)
+    }
+
+    copy_with_parents_mode(from.as_ref(), to.as_ref(), mode)
+}
+
+impl File {
+    /// Like [`File::create`], but creates the missing parent directories of `path` first.
+    ///
+    /// A failure to create the directories is traced in a `DirBuilder::create` span inside a
+    /// `File::create` span. See [`OpenOptions::create_parents`](crate::OpenOptions::create_parents)
+    /// for other options.
+    pub fn create_with_parents<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        fn create_with_parents(path: &Path) -> io::Result<File> {
+            create("File::create", path, None)?;
+            File::create(path)
+        }
+
+        create_with_parents(path.as_ref())
+    }
+
+    /// Like [`File::create_with_parents`], but creates the directories with `mode`.
+    #[cfg(unix)]
+    pub fn create_with_parents_mode<P: AsRef<Path>>(path: P, mode: u32) -> io::Result<File> {
+        fn create_with_parents_mode(path: &Path, mode: u32) -> io::Result<File> {
+            create("File::create", path, Some(mode))?;
+            File::create(path)
+        }
+
+        create_with_parents_mode(path.as_ref(), mode)
+    }
+}
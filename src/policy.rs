@@ -0,0 +1,313 @@
+//! Enforcement of a policy on the accessed paths.
+//!
+//! A [`Policy`] is an ordered list of [`Rule`]s allowing or denying accesses to the paths matching
+//! glob patterns. Once set with [`set_policy`], every wrapped call checks each of its accesses
+//! against the policy before being performed. The first matching rule decides, and the accesses
+//! matching no rule are allowed unless [`Policy::deny_by_default`] is set.
+//!
+//! A denied call fails with a `PermissionDenied` error naming the access and the rule which denied
+//! it, wrapped just like a real error so that it carries the operation and its fields.
+//!
+//! ```
+//! use fs_tracing::{audit::Access, policy::{self, Policy, Rule}};
+//! use std::io::ErrorKind;
+//!
+//! let mut policy = Policy::new();
+//! policy
+//!     .rule(Rule::deny().access(Access::Read).path("/etc/shadow"))
+//!     .rule(Rule::allow().mutating().path("/var/lib/app/**"))
+//!     .rule(Rule::deny().mutating());
+//! policy::set_policy(Some(policy));
+//!
+//! let e = fs_tracing::read("/etc/shadow").unwrap_err();
+//! assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+//! let e = fs_tracing::write("/tmp/out", "data").unwrap_err();
+//! assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+//! # policy::set_policy(None);
+//! ```
+//!
+//! Relative paths are made absolute against the current directory and `..` components are
+//! removed lexically before matching, but symbolic links are not resolved. In particular, removing
+//! `..` lexically lets `/allowed/link/../x` pass the checks as `/allowed/x` although it resolves
+//! to `x` next to the target of `link`, so the policy is not a sandbox. The patterns are
+//! matched with `*` not crossing `/`, so that `/var/lib/app/*` matches only the direct children
+//! while `/var/lib/app/**` matches everything beneath.
+
+use crate::audit::Access;
+use std::{
+    fmt, io,
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+use tracing::warn;
+
+/// Whether a policy is set, so that the wrappers can skip checking the accesses.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static POLICY: RwLock<Option<Policy>> = RwLock::new(None);
+
+const MATCH_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Sets the policy enforced by the wrappers, or removes it if `policy` is `None`.
+pub fn set_policy(policy: Option<Policy>) {
+    let mut current = POLICY.write().unwrap_or_else(|e| e.into_inner());
+    ENABLED.store(policy.is_some(), Ordering::Relaxed);
+    *current = policy;
+}
+
+/// An ordered list of rules.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+    deny_by_default: bool,
+}
+
+impl Policy {
+    /// Creates a policy allowing every access.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `rule`, which applies if none of the previous rules matches.
+    pub fn rule(&mut self, rule: &Rule) -> &mut Self {
+        self.rules.push(rule.clone());
+        self
+    }
+
+    /// Sets whether to deny the accesses matching no rule.
+    pub fn deny_by_default(&mut self, deny: bool) -> &mut Self {
+        self.deny_by_default = deny;
+        self
+    }
+
+    /// Returns the rule deciding `access` to `path` by `operation`, or `None` for the default.
+    fn decide(&self, operation: &str, access: Access, path: &Path) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(operation, access, path))
+    }
+}
+
+/// A rule allowing or denying the matching accesses.
+#[derive(Clone)]
+pub struct Rule {
+    allow: bool,
+    operation: Option<String>,
+    accesses: Vec<Access>,
+    path: Option<glob::Pattern>,
+}
+
+impl fmt::Debug for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rule")
+            .field("allow", &self.allow)
+            .field("operation", &self.operation)
+            .field("accesses", &self.accesses)
+            .field("path", &self.path.as_ref().map(|path| path.as_str()))
+            .finish()
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.allow { "allow " } else { "deny " })?;
+        if self.accesses.is_empty() {
+            f.write_str("any access")?;
+        } else {
+            for (i, access) in self.accesses.iter().enumerate() {
+                let separator = if i == 0 { "" } else { ", " };
+                write!(f, "{}{:?}", separator, access)?;
+            }
+        }
+
+        match &self.path {
+            Some(path) => write!(f, " on {}", path)?,
+            None => f.write_str(" on any path")?,
+        }
+
+        if let Some(operation) = &self.operation {
+            write!(f, " by {}", operation)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Rule {
+    /// Creates a rule allowing every access.
+    pub fn allow() -> Self {
+        Self::new(true)
+    }
+
+    /// Creates a rule denying every access.
+    pub fn deny() -> Self {
+        Self::new(false)
+    }
+
+    fn new(allow: bool) -> Self {
+        Self {
+            allow,
+            operation: None,
+            accesses: Vec::new(),
+            path: None,
+        }
+    }
+
+    /// Restricts the rule to the calls of `operation`, such as `File::open`.
+    pub fn operation(&mut self, operation: &str) -> &mut Self {
+        self.operation = Some(operation.to_string());
+        self
+    }
+
+    /// Restricts the rule to `access`. Calling this several times restricts the rule to any of
+    /// the given accesses.
+    pub fn access(&mut self, access: Access) -> &mut Self {
+        if !self.accesses.contains(&access) {
+            self.accesses.push(access);
+        }
+        self
+    }
+
+    /// Restricts the rule to the [mutating](Access::is_mutating) accesses.
+    pub fn mutating(&mut self) -> &mut Self {
+        self.access(Access::Write)
+            .access(Access::Create)
+            .access(Access::Remove)
+    }
+
+    /// Restricts the rule to the paths matching the glob `pattern`.
+    ///
+    /// # Panics
+    /// Panics if `pattern` is not a valid glob pattern.
+    pub fn path(&mut self, pattern: &str) -> &mut Self {
+        match glob::Pattern::new(pattern) {
+            Ok(pattern) => self.path = Some(pattern),
+            Err(e) => panic!("invalid glob pattern {:?}: {}", pattern, e),
+        }
+        self
+    }
+
+    fn matches(&self, operation: &str, access: Access, path: &Path) -> bool {
+        self.operation.as_deref().is_none_or(|o| o == operation)
+            && (self.accesses.is_empty() || self.accesses.contains(&access))
+            && self
+                .path
+                .as_ref()
+                .is_none_or(|pattern| pattern.matches_path_with(path, MATCH_OPTIONS))
+    }
+}
+
+/// Makes `path` absolute and removes its `.` and `..` components without touching the
+/// filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        // an unknown current directory cannot match absolute patterns anyway.
+        std::env::current_dir().unwrap_or_default().join(path)
+    };
+
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Returns the error to fail a call of `operation` on `accesses` with, if the policy denies any
+/// of them.
+pub(crate) fn check(operation: &str, accesses: &[(Access, &Path)]) -> Option<io::Error> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let policy = POLICY.read().unwrap_or_else(|e| e.into_inner());
+    let policy = policy.as_ref()?;
+    for &(access, path) in accesses {
+        let normalized = normalize(path);
+        let message = match policy.decide(operation, access, &normalized) {
+            Some(rule) if rule.allow => continue,
+            Some(rule) => format!(
+                "{:?} access to {} denied by policy rule `{}`",
+                access,
+                normalized.display(),
+                rule
+            ),
+            None if policy.deny_by_default => format!(
+                "{:?} access to {} denied by the default policy",
+                access,
+                normalized.display()
+            ),
+            None => continue,
+        };
+
+        warn!(target: "fs_tracing", operation, %message, "policy violation");
+        return Some(io::Error::new(io::ErrorKind::PermissionDenied, message));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_normalized_lexically() {
+        let cases = [
+            ("/a/b/../c", "/a/c"),
+            ("/a/./b/", "/a/b"),
+            ("/../../a", "/a"),
+            ("/a/b/../../..", "/"),
+            ("//a//b", "/a/b"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(normalize(Path::new(path)), Path::new(expected), "{}", path);
+        }
+
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(normalize(Path::new("a/../b")), cwd.join("b"));
+        assert_eq!(normalize(Path::new("")), cwd);
+    }
+
+    #[test]
+    fn the_first_matching_rule_decides() {
+        let mut policy = Policy::new();
+        policy
+            .rule(Rule::allow().access(Access::Read).path("/data/public/**"))
+            .rule(Rule::deny().path("/data/**"))
+            .rule(Rule::deny().operation("remove_file").path("/tmp/*"));
+        let allows = |operation: &str, access: Access, path: &str| {
+            policy
+                .decide(operation, access, &normalize(Path::new(path)))
+                .map(|rule| rule.allow)
+        };
+
+        assert_eq!(allows("read", Access::Read, "/data/public/a/b"), Some(true));
+        assert_eq!(
+            allows("write", Access::Write, "/data/public/a"),
+            Some(false)
+        );
+        assert_eq!(
+            allows("read", Access::Read, "/data/public/../secret"),
+            Some(false)
+        );
+        assert_eq!(allows("remove_file", Access::Remove, "/tmp/x"), Some(false));
+        // `*` does not cross `/`.
+        assert_eq!(allows("remove_file", Access::Remove, "/tmp/x/y"), None);
+        assert_eq!(allows("remove_file", Access::Remove, "/tmp/x/.."), None);
+    }
+}
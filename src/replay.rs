@@ -0,0 +1,740 @@
+//! Record and replay of the wrapped operations.
+//!
+//! A [`TraceRecorder`] writes every wrapped call made by the process to a trace file: the
+//! operation, its paths, and its outcome, which is either a summary of the result (with the length
+//! and the hash of the data read) or the error. [`replay`] loads such a trace and makes the
+//! wrappers return the recorded outcomes instead of touching the filesystem, so that a failure seen
+//! in the field can be reproduced in a test.
+//!
+//! ```no_run
+//! use fs_tracing::replay;
+//!
+//! fn run_app() -> std::io::Result<String> {
+//!     fs_tracing::read_to_string("/etc/app.conf")
+//! }
+//!
+//! # fn main() -> std::io::Result<()> {
+//! // in the field
+//! let recording = replay::TraceRecorder::new().data(true).start("/tmp/app.trace")?;
+//! let _ = run_app();
+//! drop(recording);
+//!
+//! // in a test
+//! let replaying = replay::replay("/tmp/app.trace")?;
+//! let _ = run_app();
+//! assert_eq!(replaying.remaining(), 0);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! During a replay, each call takes the first unconsumed event with the same operation and paths,
+//! and fails if there is none. Recorded errors are returned as they were. Successful results are
+//! returned when they can be reconstructed: units, counts, paths, and the data read if the trace
+//! was recorded with [`TraceRecorder::data`]. Files opened during a replay are backed by the null
+//! device, and reads through them return the recorded data, which fail the call if they do not
+//! match their recorded length and hash. A call never touches the filesystem during a replay: the
+//! ones whose results cannot be reconstructed, such as the ones returning
+//! [`Metadata`](crate::Metadata) or reading data which were not recorded, fail instead.
+//!
+//! Since the events are matched by operation and paths, a trace recorded from several threads can
+//! be replayed as long as the calls on the same paths are made in the same order.
+
+use crate::{audit::Access, output::Output};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    fmt::Write as _,
+    fs,
+    io::{self, BufRead, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+use tracing::warn;
+
+const IDLE: u8 = 0;
+const RECORDING: u8 = 1;
+const REPLAYING: u8 = 2;
+
+/// The current mode, so that the wrappers can skip locking the state when idle.
+static MODE: AtomicU8 = AtomicU8::new(IDLE);
+
+static STATE: Mutex<State> = Mutex::new(State::Idle);
+
+fn state() -> MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+enum State {
+    Idle,
+    Recording {
+        writer: BufWriter<fs::File>,
+        data: bool,
+    },
+    Replaying {
+        events: VecDeque<Event>,
+    },
+}
+
+fn start(new: State, mode: u8) -> io::Result<()> {
+    let mut state = state();
+    if let State::Idle = *state {
+        *state = new;
+        MODE.store(mode, Ordering::Relaxed);
+        Ok(())
+    } else {
+        Err(io::Error::other(
+            "a recording or a replay is already in progress",
+        ))
+    }
+}
+
+fn stop() -> State {
+    MODE.store(IDLE, Ordering::Relaxed);
+    std::mem::replace(&mut *state(), State::Idle)
+}
+
+/// Options for recording a trace.
+#[derive(Debug, Clone)]
+pub struct TraceRecorder {
+    data: bool,
+}
+
+impl TraceRecorder {
+    /// Creates options recording only the hashes of the data read.
+    pub fn new() -> Self {
+        Self { data: false }
+    }
+
+    /// Sets whether to record the data read in addition to their hashes.
+    ///
+    /// The data are needed to replay successful reads, but can make the trace large.
+    pub fn data(&mut self, data: bool) -> &mut Self {
+        self.data = data;
+        self
+    }
+
+    /// Starts recording the wrapped calls of the process to a trace file at `path`, until the
+    /// returned guard is dropped.
+    ///
+    /// The trace file itself is written without going through the wrappers.
+    pub fn start<P: AsRef<Path>>(&self, path: P) -> io::Result<RecordingGuard> {
+        let writer = BufWriter::new(fs::File::create(path)?);
+        start(
+            State::Recording {
+                writer,
+                data: self.data,
+            },
+            RECORDING,
+        )?;
+        Ok(RecordingGuard { _private: () })
+    }
+}
+
+/// A guard stopping the recording and flushing the trace file when dropped.
+#[derive(Debug)]
+#[must_use = "the recording stops when the guard is dropped"]
+pub struct RecordingGuard {
+    _private: (),
+}
+
+impl Drop for RecordingGuard {
+    fn drop(&mut self) {
+        if let State::Recording { mut writer, .. } = stop() {
+            if let Err(e) = writer.flush() {
+                warn!(target: "fs_tracing", error = %e, "failed to flush the recorded trace");
+            }
+        }
+    }
+}
+
+/// Starts replaying the trace file at `path`, until the returned guard is dropped.
+pub fn replay<P: AsRef<Path>>(path: P) -> io::Result<ReplayGuard> {
+    let mut events = VecDeque::new();
+    for (number, line) in io::BufReader::new(fs::File::open(path)?)
+        .lines()
+        .enumerate()
+    {
+        let event = Event::parse(&line?).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed event at line {}", number + 1),
+            )
+        })?;
+        events.push_back(event);
+    }
+
+    start(State::Replaying { events }, REPLAYING)?;
+    Ok(ReplayGuard { _private: () })
+}
+
+/// A guard stopping the replay when dropped.
+#[derive(Debug)]
+#[must_use = "the replay stops when the guard is dropped"]
+pub struct ReplayGuard {
+    _private: (),
+}
+
+impl ReplayGuard {
+    /// Returns the number of recorded events which have not been replayed yet.
+    pub fn remaining(&self) -> usize {
+        match &*state() {
+            State::Replaying { events } => events.len(),
+            _ => 0,
+        }
+    }
+}
+
+impl Drop for ReplayGuard {
+    fn drop(&mut self) {
+        stop();
+    }
+}
+
+/// The recorded summary of a successful result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Value {
+    Unit,
+    Count(u64),
+    /// The result is the data read, which are recorded separately.
+    Bytes,
+    Path(PathBuf),
+    /// The result cannot be reconstructed.
+    Opaque,
+}
+
+#[derive(Debug)]
+enum Outcome {
+    Ok(Value),
+    Os(i32),
+    Kind(io::ErrorKind, String),
+}
+
+#[derive(Debug)]
+struct Data {
+    len: u64,
+    hash: u64,
+    bytes: Option<Vec<u8>>,
+}
+
+/// A recorded call.
+#[derive(Debug)]
+pub(crate) struct Event {
+    operation: String,
+    paths: Vec<PathBuf>,
+    outcome: Outcome,
+    data: Option<Data>,
+}
+
+/// The caller-provided buffers filled by reads.
+pub(crate) trait Buffer {
+    /// Returns the position from which a read starts filling the buffer.
+    fn mark(&self) -> usize;
+
+    /// Returns the data filled by a read started at `mark` which returned `count` if any.
+    fn filled(&self, mark: usize, count: Option<u64>) -> Cow<'_, [u8]>;
+
+    /// Fills the buffer with replayed data.
+    fn fill(&mut self, data: &[u8]) -> io::Result<()>;
+}
+
+impl Buffer for [u8] {
+    fn mark(&self) -> usize {
+        0
+    }
+
+    fn filled(&self, _mark: usize, count: Option<u64>) -> Cow<'_, [u8]> {
+        match count {
+            Some(count) => Cow::Borrowed(&self[..(count as usize).min(self.len())]),
+            None => Cow::Borrowed(self),
+        }
+    }
+
+    fn fill(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.len() > self.len() {
+            return Err(diverged("the replayed data do not fit in the buffer"));
+        }
+        self[..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+impl Buffer for Vec<u8> {
+    fn mark(&self) -> usize {
+        self.len()
+    }
+
+    fn filled(&self, mark: usize, _count: Option<u64>) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self[mark.min(self.len())..])
+    }
+
+    fn fill(&mut self, data: &[u8]) -> io::Result<()> {
+        self.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+impl Buffer for String {
+    fn mark(&self) -> usize {
+        self.len()
+    }
+
+    fn filled(&self, mark: usize, _count: Option<u64>) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.as_bytes()[mark.min(self.len())..])
+    }
+
+    fn fill(&mut self, data: &[u8]) -> io::Result<()> {
+        let data =
+            std::str::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.push_str(data);
+        Ok(())
+    }
+}
+
+impl Buffer for [io::IoSliceMut<'_>] {
+    fn mark(&self) -> usize {
+        0
+    }
+
+    fn filled(&self, _mark: usize, count: Option<u64>) -> Cow<'_, [u8]> {
+        let mut remaining = count.map_or(usize::MAX, |count| count as usize);
+        let mut filled = Vec::new();
+        for buf in self {
+            let len = buf.len().min(remaining);
+            filled.extend_from_slice(&buf[..len]);
+            remaining -= len;
+        }
+        Cow::Owned(filled)
+    }
+
+    fn fill(&mut self, mut data: &[u8]) -> io::Result<()> {
+        for buf in self.iter_mut() {
+            let len = buf.len().min(data.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            data = &data[len..];
+        }
+        if data.is_empty() {
+            Ok(())
+        } else {
+            Err(diverged("the replayed data do not fit in the buffers"))
+        }
+    }
+}
+
+pub(crate) fn diverged(message: &str) -> io::Error {
+    io::Error::other(message)
+}
+
+pub(crate) fn mismatched() -> io::Error {
+    diverged("the recorded result does not match the operation")
+}
+
+pub(crate) fn unrecorded() -> io::Error {
+    diverged("the data read are not recorded, see `TraceRecorder::data`")
+}
+
+fn same_paths(paths: &[PathBuf], accesses: &[(Access, &Path)]) -> bool {
+    paths.len() == accesses.len()
+        && paths
+            .iter()
+            .zip(accesses)
+            .all(|(path, (_, accessed))| path == accessed)
+}
+
+/// Returns the outcome to replay for a call of `operation` on `accesses`, or `None` if not
+/// replaying.
+pub(crate) fn take(operation: &str, accesses: &[(Access, &Path)]) -> Option<Option<Event>> {
+    if MODE.load(Ordering::Relaxed) != REPLAYING {
+        return None;
+    }
+
+    match &mut *state() {
+        State::Replaying { events } => Some(
+            events
+                .iter()
+                .position(|event| {
+                    event.operation == operation && same_paths(&event.paths, accesses)
+                })
+                .and_then(|index| events.remove(index)),
+        ),
+        _ => None,
+    }
+}
+
+/// Reconstructs the result of a call from a replayed `event`, or fails if the event is missing or
+/// its result cannot be reconstructed.
+pub(crate) fn result<T: Output, B: Buffer + ?Sized>(
+    event: Option<Event>,
+    buffer: Option<&mut B>,
+) -> io::Result<T> {
+    let event =
+        event.ok_or_else(|| diverged("the operation is not found in the replayed trace"))?;
+
+    let value = match event.outcome {
+        Outcome::Ok(value) => value,
+        Outcome::Os(code) => return Err(io::Error::from_raw_os_error(code)),
+        Outcome::Kind(kind, message) => {
+            return Err(if message == io::Error::from(kind).to_string() {
+                io::Error::from(kind)
+            } else {
+                io::Error::new(kind, message)
+            })
+        }
+    };
+
+    let data = match &event.data {
+        Some(Data {
+            len,
+            hash: recorded,
+            bytes: Some(bytes),
+        }) => {
+            if bytes.len() as u64 != *len || hash(bytes) != *recorded {
+                return Err(diverged(
+                    "the recorded data do not match their recorded length and hash",
+                ));
+            }
+            Some(bytes.as_slice())
+        }
+        _ => None,
+    };
+    let result = T::replay(&value, data, &event.paths)?;
+    if let Some(buffer) = buffer {
+        buffer.fill(data.ok_or_else(unrecorded)?)?;
+    }
+
+    Ok(result)
+}
+
+/// Records the outcome of a call of `operation` on `accesses` if recording.
+pub(crate) fn record<T: Output, B: Buffer + ?Sized>(
+    operation: &str,
+    accesses: &[(Access, &Path)],
+    result: &io::Result<T>,
+    buffer: Option<(&B, usize)>,
+) {
+    if MODE.load(Ordering::Relaxed) != RECORDING {
+        return;
+    }
+
+    let mut state = state();
+    let (writer, capture) = match &mut *state {
+        State::Recording { writer, data } => (writer, *data),
+        _ => return,
+    };
+
+    let (outcome, data) = match result {
+        Ok(value) => {
+            let (summary, data) = value.record();
+            let count = match summary {
+                Value::Count(count) => Some(count),
+                _ => None,
+            };
+            let data = data
+                .map(Cow::Borrowed)
+                .or_else(|| buffer.map(|(buffer, mark)| buffer.filled(mark, count)));
+            (Outcome::Ok(summary), data)
+        }
+        Err(e) => match e.raw_os_error() {
+            Some(code) => (Outcome::Os(code), None),
+            None => (Outcome::Kind(e.kind(), e.to_string()), None),
+        },
+    };
+
+    let event = Event {
+        operation: operation.to_string(),
+        paths: accesses
+            .iter()
+            .map(|(_, path)| path.to_path_buf())
+            .collect(),
+        outcome,
+        data: data.map(|data| Data {
+            len: data.len() as u64,
+            hash: hash(&data),
+            bytes: if capture {
+                Some(data.into_owned())
+            } else {
+                None
+            },
+        }),
+    };
+
+    if let Err(e) = writeln!(writer, "{}", event.format()) {
+        warn!(target: "fs_tracing", error = %e, "failed to record an event");
+    }
+}
+
+/// FNV-1a, which is stable across platforms and releases unlike `DefaultHasher`.
+fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The error kinds which can be recorded by name. Others are replayed as `Other`.
+const KINDS: &[io::ErrorKind] = &[
+    io::ErrorKind::NotFound,
+    io::ErrorKind::PermissionDenied,
+    io::ErrorKind::AlreadyExists,
+    io::ErrorKind::WouldBlock,
+    io::ErrorKind::InvalidInput,
+    io::ErrorKind::InvalidData,
+    io::ErrorKind::TimedOut,
+    io::ErrorKind::WriteZero,
+    io::ErrorKind::Interrupted,
+    io::ErrorKind::Unsupported,
+    io::ErrorKind::UnexpectedEof,
+    io::ErrorKind::OutOfMemory,
+    io::ErrorKind::IsADirectory,
+    io::ErrorKind::NotADirectory,
+    io::ErrorKind::DirectoryNotEmpty,
+    io::ErrorKind::ReadOnlyFilesystem,
+    io::ErrorKind::StorageFull,
+    io::ErrorKind::ResourceBusy,
+    io::ErrorKind::CrossesDevices,
+    io::ErrorKind::StaleNetworkFileHandle,
+    io::ErrorKind::Other,
+];
+
+/// Encodes `bytes` into a token without whitespace. The leading `'` keeps empty strings apart.
+fn encode(bytes: &[u8], out: &mut String) {
+    out.push('\'');
+    for &byte in bytes {
+        if byte.is_ascii_graphic() && byte != b'%' {
+            out.push(char::from(byte));
+        } else {
+            let _ = write!(out, "%{:02x}", byte);
+        }
+    }
+}
+
+fn decode(token: &str) -> Option<Vec<u8>> {
+    let mut bytes = token.strip_prefix('\'')?.bytes();
+    let mut decoded = Vec::new();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            decoded.push(byte);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Event {
+    // <operation> <#paths> <path>... ok <value> [data <len> <hash> ['<hex>]]
+    // <operation> <#paths> <path>... err os <code>
+    // <operation> <#paths> <path>... err kind <kind> <message>
+    fn format(&self) -> String {
+        let mut line = String::new();
+        encode(self.operation.as_bytes(), &mut line);
+        let _ = write!(line, " {}", self.paths.len());
+        for path in &self.paths {
+            line.push(' ');
+            encode(&path_to_bytes(path), &mut line);
+        }
+
+        match &self.outcome {
+            Outcome::Ok(Value::Unit) => line.push_str(" ok unit"),
+            Outcome::Ok(Value::Count(count)) => {
+                let _ = write!(line, " ok count {}", count);
+            }
+            Outcome::Ok(Value::Bytes) => line.push_str(" ok bytes"),
+            Outcome::Ok(Value::Path(path)) => {
+                line.push_str(" ok path ");
+                encode(&path_to_bytes(path), &mut line);
+            }
+            Outcome::Ok(Value::Opaque) => line.push_str(" ok opaque"),
+            Outcome::Os(code) => {
+                let _ = write!(line, " err os {}", code);
+            }
+            Outcome::Kind(kind, message) => {
+                let _ = write!(line, " err kind {:?} ", kind);
+                encode(message.as_bytes(), &mut line);
+            }
+        }
+
+        if let Some(data) = &self.data {
+            let _ = write!(line, " data {} {:016x}", data.len, data.hash);
+            if let Some(bytes) = &data.bytes {
+                // the leading `'` keeps captured empty data apart from uncaptured data.
+                let _ = write!(line, " '{}", to_hex(bytes));
+            }
+        }
+
+        line
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut tokens = line.split_whitespace();
+        let operation = String::from_utf8(decode(tokens.next()?)?).ok()?;
+        let count: usize = tokens.next()?.parse().ok()?;
+        let paths = (0..count)
+            .map(|_| Some(path_from_bytes(decode(tokens.next()?)?)))
+            .collect::<Option<Vec<_>>>()?;
+
+        let outcome = match (tokens.next()?, tokens.next()?) {
+            ("ok", "unit") => Outcome::Ok(Value::Unit),
+            ("ok", "count") => Outcome::Ok(Value::Count(tokens.next()?.parse().ok()?)),
+            ("ok", "bytes") => Outcome::Ok(Value::Bytes),
+            ("ok", "path") => Outcome::Ok(Value::Path(path_from_bytes(decode(tokens.next()?)?))),
+            ("ok", "opaque") => Outcome::Ok(Value::Opaque),
+            ("err", "os") => Outcome::Os(tokens.next()?.parse().ok()?),
+            ("err", "kind") => {
+                let name = tokens.next()?;
+                let kind = KINDS
+                    .iter()
+                    .copied()
+                    .find(|kind| format!("{:?}", kind) == name)
+                    .unwrap_or(io::ErrorKind::Other);
+                Outcome::Kind(kind, String::from_utf8(decode(tokens.next()?)?).ok()?)
+            }
+            _ => return None,
+        };
+
+        let data = match tokens.next() {
+            Some("data") => Some(Data {
+                len: tokens.next()?.parse().ok()?,
+                hash: u64::from_str_radix(tokens.next()?, 16).ok()?,
+                bytes: match tokens.next() {
+                    Some(hex) => Some(from_hex(hex.strip_prefix('\'')?)?),
+                    None => None,
+                },
+            }),
+            Some(_) => return None,
+            None => None,
+        };
+
+        Some(Self {
+            operation,
+            paths,
+            outcome,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(outcome: Outcome, data: Option<Data>) -> Event {
+        Event {
+            operation: "File::read".to_string(),
+            paths: vec![PathBuf::from("/tmp/with space/%odd"), PathBuf::from("")],
+            outcome,
+            data,
+        }
+    }
+
+    fn data(bytes: &[u8], capture: bool) -> Data {
+        Data {
+            len: bytes.len() as u64,
+            hash: hash(bytes),
+            bytes: if capture { Some(bytes.to_vec()) } else { None },
+        }
+    }
+
+    #[test]
+    fn events_round_trip() {
+        let events = [
+            event(Outcome::Ok(Value::Unit), None),
+            event(
+                Outcome::Ok(Value::Count(42)),
+                Some(data(b"\0\xffabc", true)),
+            ),
+            event(Outcome::Ok(Value::Bytes), Some(data(b"", true))),
+            event(Outcome::Ok(Value::Bytes), Some(data(b"hashed only", false))),
+            event(Outcome::Ok(Value::Path(PathBuf::from("/a b"))), None),
+            event(Outcome::Ok(Value::Opaque), None),
+            event(Outcome::Os(2), None),
+            event(
+                Outcome::Kind(io::ErrorKind::NotFound, "gone away".to_string()),
+                None,
+            ),
+        ];
+
+        for event in &events {
+            let line = event.format();
+            assert!(!line.contains('\n'), "{}", line);
+            let parsed = Event::parse(&line).unwrap();
+            assert_eq!(format!("{:?}", parsed), format!("{:?}", event));
+        }
+    }
+
+    #[test]
+    fn malformed_events_are_rejected() {
+        for line in [
+            "",
+            "'op",
+            "'op 1",
+            "'op 0 ok",
+            "'op 0 ok count x",
+            "'op 0 ok unit junk",
+        ] {
+            assert!(Event::parse(line).is_none(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn replayed_data_are_checked_against_their_hash() {
+        let replayed = result::<usize, [u8]>(
+            Some(event(
+                Outcome::Ok(Value::Count(3)),
+                Some(data(b"abc", true)),
+            )),
+            Some(&mut [0; 3][..]),
+        );
+        assert_eq!(replayed.unwrap(), 3);
+
+        let mut tampered = data(b"abc", true);
+        tampered.bytes = Some(b"abd".to_vec());
+        let replayed = result::<usize, [u8]>(
+            Some(event(Outcome::Ok(Value::Count(3)), Some(tampered))),
+            Some(&mut [0; 3][..]),
+        );
+        assert_eq!(replayed.unwrap_err().kind(), io::ErrorKind::Other);
+    }
+}
@@ -0,0 +1,207 @@
+//! Retries of the operations failing with transient errors.
+//!
+//! A [`RetryPolicy`] tells which errors are transient, how many times a wrapped call is attempted,
+//! and how long to wait between the attempts. It applies either to every wrapped call with
+//! [`set_policy`], or to the calls made by a closure on the current thread with [`retry`], which
+//! takes precedence.
+//!
+//! ```
+//! use fs_tracing::retry::{self, RetryPolicy};
+//! use std::time::Duration;
+//!
+//! let mut policy = RetryPolicy::new();
+//! policy.attempts(5).backoff(Duration::from_millis(20));
+//!
+//! let contents = retry::retry(&policy, || fs_tracing::read("/etc/hosts"));
+//! # let _ = contents;
+//! ```
+//!
+//! Each retry emits a `WARN` event with the operation name, the attempt, the error, and the same
+//! fields that would be recorded on error. A call failing after several attempts records their
+//! number in the `attempts` field of its span.
+//!
+//! The calls which may have partially taken effect before failing, such as `File::read_exact`,
+//! `File::read_to_end`, `File::read_to_string`, `File::write_all`, `File::write_fmt` and
+//! `ReadDir::next`, are never retried.
+
+use std::{
+    cell::RefCell,
+    io,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
+
+/// Whether a global policy is set, so that the wrappers can skip looking up the policy.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static POLICY: RwLock<Option<RetryPolicy>> = RwLock::new(None);
+
+/// The number of threads running [`retry`], so that the wrappers can skip looking up the
+/// thread-local policies when there are none.
+static SCOPED: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static POLICIES: RefCell<Vec<RetryPolicy>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Which errors to retry and how.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    kinds: Vec<io::ErrorKind>,
+    os_errors: Vec<i32>,
+}
+
+impl RetryPolicy {
+    /// Creates a policy attempting a call 3 times, waiting 10ms before the first retry and twice
+    /// as long before each of the next ones up to 1s.
+    ///
+    /// The errors of kinds `Interrupted`, `WouldBlock`, `ResourceBusy` and
+    /// `StaleNetworkFileHandle` (`EINTR`, `EAGAIN`, `EBUSY` and `ESTALE` on Unix) are transient.
+    pub fn new() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            kinds: vec![
+                io::ErrorKind::Interrupted,
+                io::ErrorKind::WouldBlock,
+                io::ErrorKind::ResourceBusy,
+                io::ErrorKind::StaleNetworkFileHandle,
+            ],
+            os_errors: Vec::new(),
+        }
+    }
+
+    /// Sets the maximum number of attempts, including the first one.
+    pub fn attempts(&mut self, attempts: u32) -> &mut Self {
+        self.attempts = attempts;
+        self
+    }
+
+    /// Sets the time to wait before the first retry. The time doubles for each of the next ones.
+    pub fn backoff(&mut self, backoff: Duration) -> &mut Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Sets the maximum time to wait before a retry.
+    pub fn max_backoff(&mut self, max_backoff: Duration) -> &mut Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Considers the errors of `kind` transient.
+    pub fn transient_kind(&mut self, kind: io::ErrorKind) -> &mut Self {
+        if !self.kinds.contains(&kind) {
+            self.kinds.push(kind);
+        }
+        self
+    }
+
+    /// Considers the OS error `errno` transient.
+    pub fn transient_os_error(&mut self, errno: i32) -> &mut Self {
+        if !self.os_errors.contains(&errno) {
+            self.os_errors.push(errno);
+        }
+        self
+    }
+
+    /// Considers no error transient, so that only the errors added afterwards are retried.
+    pub fn clear_transient(&mut self) -> &mut Self {
+        self.kinds.clear();
+        self.os_errors.clear();
+        self
+    }
+
+    fn is_transient(&self, error: &io::Error) -> bool {
+        self.kinds.contains(&error.kind())
+            || error
+                .raw_os_error()
+                .is_some_and(|errno| self.os_errors.contains(&errno))
+    }
+
+    /// Returns the time to wait before retrying after the failure of the `attempt`th attempt, or
+    /// `None` if the call must not be retried.
+    fn delay(&self, error: &io::Error, attempt: u32) -> Option<Duration> {
+        if attempt >= self.attempts || !self.is_transient(error) {
+            return None;
+        }
+
+        let factor = 2u32.saturating_pow(attempt - 1);
+        Some(self.backoff.saturating_mul(factor).min(self.max_backoff))
+    }
+}
+
+/// Sets the policy applied to every wrapped call, or removes it if `policy` is `None`.
+pub fn set_policy(policy: Option<RetryPolicy>) {
+    let mut current = POLICY.write().unwrap_or_else(|e| e.into_inner());
+    ENABLED.store(policy.is_some(), Ordering::Relaxed);
+    *current = policy;
+}
+
+/// Runs `f`, applying `policy` to the wrapped calls it makes on the current thread.
+pub fn retry<R, F: FnOnce() -> R>(policy: &RetryPolicy, f: F) -> R {
+    POLICIES.with(|policies| {
+        let mut policies = policies.borrow_mut();
+        if policies.is_empty() {
+            SCOPED.fetch_add(1, Ordering::Relaxed);
+        }
+        policies.push(policy.clone());
+    });
+
+    // pops the policy even if `f` panics.
+    let _guard = Scope {
+        _not_send: PhantomData,
+    };
+    f()
+}
+
+struct Scope {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        POLICIES.with(|policies| {
+            let mut policies = policies.borrow_mut();
+            policies.pop();
+            if policies.is_empty() {
+                SCOPED.fetch_sub(1, Ordering::Relaxed);
+            }
+        });
+    }
+}
+
+/// Returns the time to wait before retrying a call after the failure of its `attempt`th attempt
+/// with `error`, or `None` if the call must not be retried.
+pub(crate) fn delay(error: &io::Error, attempt: u32) -> Option<Duration> {
+    let scoped = SCOPED.load(Ordering::Relaxed) > 0;
+    if !scoped && !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    if scoped {
+        let delay = POLICIES.with(|policies| {
+            policies
+                .borrow()
+                .last()
+                .map(|policy| policy.delay(error, attempt))
+        });
+        if let Some(delay) = delay {
+            return delay;
+        }
+    }
+
+    POLICY
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()?
+        .delay(error, attempt)
+}
@@ -0,0 +1,113 @@
+//! Detection of slow operations.
+//!
+//! When a wrapped call takes longer than the threshold configured for its operation, fs-tracing
+//! emits a `WARN` event with the operation name, the elapsed time, the threshold, and the same
+//! fields that would be recorded on error (such as the paths).
+//!
+//! Operations are named as in their spans, such as `rename`, `read_dir` or `File::sync_all`.
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! fs_tracing::slow::set_default_threshold(Some(Duration::from_secs(1)));
+//! fs_tracing::slow::set_threshold("File::sync_all", Some(Duration::from_secs(5)));
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
+
+/// Whether any threshold is configured, so that the wrappers can skip looking up the thresholds.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static THRESHOLDS: RwLock<Thresholds> = RwLock::new(Thresholds {
+    default: None,
+    operations: None,
+});
+
+struct Thresholds {
+    default: Option<Duration>,
+    // `HashMap::new` is not const.
+    operations: Option<HashMap<String, Duration>>,
+}
+
+impl Thresholds {
+    fn get(&self, operation: &str) -> Option<Duration> {
+        self.operations
+            .as_ref()
+            .and_then(|operations| operations.get(operation).copied())
+            .or(self.default)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.default.is_none()
+            && self
+                .operations
+                .as_ref()
+                .is_none_or(|operations| operations.is_empty())
+    }
+}
+
+fn update(f: impl FnOnce(&mut Thresholds)) {
+    let mut thresholds = THRESHOLDS.write().unwrap_or_else(|e| e.into_inner());
+    f(&mut thresholds);
+    ENABLED.store(!thresholds.is_empty(), Ordering::Relaxed);
+}
+
+/// Sets the threshold for `operation`, or removes it if `threshold` is `None`.
+///
+/// A threshold set for an operation takes precedence over the default threshold.
+pub fn set_threshold(operation: &str, threshold: Option<Duration>) {
+    update(|thresholds| {
+        let operations = thresholds.operations.get_or_insert_with(HashMap::new);
+        match threshold {
+            Some(threshold) => {
+                operations.insert(operation.to_string(), threshold);
+            }
+            None => {
+                operations.remove(operation);
+            }
+        }
+    });
+}
+
+/// Sets the threshold for the operations without their own thresholds, or removes it if
+/// `threshold` is `None`.
+pub fn set_default_threshold(threshold: Option<Duration>) {
+    update(|thresholds| thresholds.default = threshold);
+}
+
+/// Removes all the thresholds.
+pub fn clear_thresholds() {
+    update(|thresholds| {
+        thresholds.default = None;
+        thresholds.operations = None;
+    });
+}
+
+/// Returns whether any threshold is configured, so that the wrappers need to time the calls.
+pub(crate) fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns the threshold for `operation` if `elapsed` exceeds it.
+pub(crate) fn exceeded(operation: &str, elapsed: Duration) -> Option<Duration> {
+    if !is_enabled() {
+        return None;
+    }
+
+    let threshold = THRESHOLDS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(operation)?;
+    if elapsed > threshold {
+        Some(threshold)
+    } else {
+        None
+    }
+}
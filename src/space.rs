@@ -0,0 +1,254 @@
+use crate::{audit::Access, Metadata};
+#[cfg(unix)]
+use std::collections::HashSet;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tracing::debug;
+
+/// The capacity of a filesystem, returned by [`statvfs`].
+#[cfg(all(feature = "statvfs", unix))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Statvfs {
+    total_space: u64,
+    free_space: u64,
+    available_space: u64,
+    total_files: u64,
+    free_files: u64,
+}
+
+#[cfg(all(feature = "statvfs", unix))]
+impl Statvfs {
+    /// Returns the size of the filesystem in bytes.
+    pub fn total_space(&self) -> u64 {
+        self.total_space
+    }
+
+    /// Returns the number of free bytes, including the ones reserved for the superuser.
+    pub fn free_space(&self) -> u64 {
+        self.free_space
+    }
+
+    /// Returns the number of bytes available to unprivileged users.
+    pub fn available_space(&self) -> u64 {
+        self.available_space
+    }
+
+    /// Returns the number of inodes of the filesystem.
+    pub fn total_files(&self) -> u64 {
+        self.total_files
+    }
+
+    /// Returns the number of free inodes.
+    pub fn free_files(&self) -> u64 {
+        self.free_files
+    }
+}
+
+/// Returns the capacity of the filesystem containing `path`.
+///
+/// Wrapper for [`statvfs(3)`](https://man7.org/linux/man-pages/man3/statvfs.3.html).
+#[cfg(all(feature = "statvfs", unix))]
+pub fn statvfs<P: AsRef<Path>>(path: P) -> io::Result<Statvfs> {
+    // the widths of the fields depend on the platform.
+    #[allow(clippy::unnecessary_cast)]
+    fn statvfs(path: &Path) -> io::Result<Statvfs> {
+        traced!(
+            "statvfs" [Access::Metadata => path],
+            nix::sys::statvfs::statvfs(path)
+                .map_err(io::Error::from)
+                .map(|stat| {
+                    let fragment = stat.fragment_size() as u64;
+                    Statvfs {
+                        total_space: stat.blocks() as u64 * fragment,
+                        free_space: stat.blocks_free() as u64 * fragment,
+                        available_space: stat.blocks_available() as u64 * fragment,
+                        total_files: stat.files() as u64,
+                        free_files: stat.files_free() as u64,
+                    }
+                }),
+            ?path
+        )
+    }
+
+    statvfs(path.as_ref())
+}
+
+/// Returns the number of bytes available to unprivileged users on the filesystem containing
+/// `path`.
+///
+/// See [`statvfs`].
+#[cfg(all(feature = "statvfs", unix))]
+pub fn available_space<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    statvfs(path).map(|stat| stat.available_space())
+}
+
+/// Options for [`disk_usage`].
+#[derive(Debug, Clone)]
+pub struct DiskUsageOptions {
+    skip_errors: bool,
+}
+
+impl DiskUsageOptions {
+    /// Creates options failing on the first unreadable entry.
+    pub fn new() -> Self {
+        Self { skip_errors: false }
+    }
+
+    /// Sets whether to skip the unreadable entries instead of failing, counting them in
+    /// [`DiskUsage::skipped`].
+    pub fn skip_errors(&mut self, skip: bool) -> &mut Self {
+        self.skip_errors = skip;
+        self
+    }
+}
+
+/// The sizes summed by [`disk_usage`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    apparent_size: u64,
+    allocated_size: u64,
+    entries: u64,
+    skipped: u64,
+}
+
+impl DiskUsage {
+    /// Returns the sum of the lengths of the entries, as `du --apparent-size`.
+    pub fn apparent_size(&self) -> u64 {
+        self.apparent_size
+    }
+
+    /// Returns the sum of the space allocated to the entries, as `du`.
+    ///
+    /// On platforms other than Unix, it is the same as the apparent size.
+    pub fn allocated_size(&self) -> u64 {
+        self.allocated_size
+    }
+
+    /// Returns the number of entries counted, including the directories.
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// Returns the number of entries skipped with [`DiskUsageOptions::skip_errors`]. The
+    /// contents of a skipped directory are not counted.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+/// Sums the sizes of `path` and its descendants, without following symbolic links.
+///
+/// A file with several hard links is counted once. Each failure is traced in a `disk_usage`
+/// span with the entry and the failing `step`, `metadata` or `read_dir`.
+///
+/// ```no_run
+/// use fs_tracing::DiskUsageOptions;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let usage = fs_tracing::disk_usage("/var/cache", DiskUsageOptions::new().skip_errors(true))?;
+/// println!("{} bytes, {} entries skipped", usage.allocated_size(), usage.skipped());
+/// # Ok(())
+/// # }
+/// ```
+pub fn disk_usage<P: AsRef<Path>>(path: P, options: &DiskUsageOptions) -> io::Result<DiskUsage> {
+    fn disk_usage(path: &Path, options: &DiskUsageOptions) -> io::Result<DiskUsage> {
+        let mut walk = Walk {
+            options,
+            usage: DiskUsage::default(),
+            #[cfg(unix)]
+            linked: HashSet::new(),
+        };
+        // the root is never skipped.
+        let metadata = walk.metadata(path)?;
+        walk.entry(path, &metadata)?;
+        Ok(walk.usage)
+    }
+
+    disk_usage(path.as_ref(), options)
+}
+
+struct Walk<'a> {
+    options: &'a DiskUsageOptions,
+    usage: DiskUsage,
+    // the (device, inode) pairs of the files with several links which are already counted.
+    #[cfg(unix)]
+    linked: HashSet<(u64, u64)>,
+}
+
+impl Walk<'_> {
+    /// Counts `error` as skipped, or returns it if the errors are not skipped.
+    fn fail(&mut self, error: io::Error) -> io::Result<()> {
+        if self.options.skip_errors {
+            debug!(target: "fs_tracing", error = %error, "skipped unreadable entry");
+            self.usage.skipped += 1;
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        traced!(
+            "disk_usage" [Access::Metadata => path],
+            fs::symlink_metadata(path).map(|inner| Metadata { inner }),
+            step = "metadata",
+            ?path
+        )
+    }
+
+    fn entry(&mut self, path: &Path, metadata: &Metadata) -> io::Result<()> {
+        self.count(&metadata.inner);
+        if !metadata.is_dir() {
+            return Ok(());
+        }
+
+        let entries = traced!(
+            "disk_usage" [Access::List => path],
+            fs::read_dir(path).and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.file_name().into()))
+                    .collect::<io::Result<Vec<PathBuf>>>()
+            }),
+            step = "read_dir",
+            ?path
+        );
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(e) => return self.fail(e),
+        };
+
+        for name in entries {
+            let path = path.join(name);
+            match self.metadata(&path) {
+                Ok(metadata) => self.entry(&path, &metadata)?,
+                Err(e) => self.fail(e)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn count(&mut self, metadata: &fs::Metadata) {
+        #[cfg(unix)]
+        let allocated = {
+            use std::os::unix::fs::MetadataExt;
+
+            if metadata.nlink() > 1
+                && !metadata.is_dir()
+                && !self.linked.insert((metadata.dev(), metadata.ino()))
+            {
+                return;
+            }
+            // `st_blocks` is in units of 512 bytes regardless of the block size.
+            metadata.blocks() * 512
+        };
+        #[cfg(not(unix))]
+        let allocated = metadata.len();
+
+        self.usage.entries += 1;
+        self.usage.apparent_size += metadata.len();
+        self.usage.allocated_size += allocated;
+    }
+}
@@ -0,0 +1,367 @@
+//! In-process statistics of the operations wrapped by fs-tracing.
+//!
+//! Once enabled with [`set_enabled`], every wrapper counts its calls, its errors by
+//! [`io::ErrorKind`](std::io::ErrorKind), the bytes it read or wrote, and its latency. The
+//! statistics are aggregated by operation name, which is the same name as the span recorded on
+//! error (such as `read` or `File::write_all`). The latency of the calls blocking until something
+//! happens, such as `Watcher::read`, is not recorded.
+//!
+//! ```
+//! use std::io::ErrorKind;
+//!
+//! fs_tracing::stats::set_enabled(true);
+//! fs_tracing::read("/not_exist").unwrap_err();
+//!
+//! let snapshot = fs_tracing::stats::snapshot();
+//! let read = snapshot.get("read").unwrap();
+//! assert!(read.calls() >= 1);
+//! assert!(read.errors_of(ErrorKind::NotFound) >= 1);
+//! ```
+//!
+//! With the `metrics` feature enabled, the statistics are also reported to the
+//! [`metrics`](https://docs.rs/metrics) facade as the counters `fs_tracing.calls`,
+//! `fs_tracing.errors`, `fs_tracing.bytes_read` and `fs_tracing.bytes_written`, and the histogram
+//! `fs_tracing.latency_seconds`, all labeled with `operation`.
+//!
+//! The statistics are disabled by default, so that the successful path of the wrappers neither
+//! reads the clock nor updates any counter unless asked to.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+
+/// Whether the statistics are collected, so that the wrappers can skip timing the calls.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The number of buckets in a [`Histogram`].
+const BUCKETS: usize = 24;
+
+static CALLSITES: Mutex<Vec<&'static Callsite>> = Mutex::new(Vec::new());
+
+fn callsites() -> MutexGuard<'static, Vec<&'static Callsite>> {
+    CALLSITES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Statistics of a single call site of a wrapper.
+///
+/// Call sites register themselves on their first call, and are merged by name in [`snapshot`].
+pub(crate) struct Callsite {
+    name: &'static str,
+    registered: AtomicBool,
+    calls: AtomicU64,
+    // errors are on the slow path anyway.
+    errors: Mutex<Vec<(io::ErrorKind, u64)>>,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    latency_sum: AtomicU64,
+    latency_buckets: [AtomicU64; BUCKETS],
+}
+
+impl Callsite {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+
+    pub(crate) const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            registered: AtomicBool::new(false),
+            calls: AtomicU64::new(0),
+            errors: Mutex::new(Vec::new()),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            latency_sum: AtomicU64::new(0),
+            latency_buckets: [Self::ZERO; BUCKETS],
+        }
+    }
+
+    /// Records a call which took `elapsed` if it was timed, and failed with `error` if any.
+    pub(crate) fn record(&'static self, elapsed: Option<Duration>, error: Option<io::ErrorKind>) {
+        if !is_enabled() {
+            return;
+        }
+        if !self.registered.swap(true, Ordering::Relaxed) {
+            callsites().push(self);
+        }
+
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        if let Some(elapsed) = elapsed {
+            let nanos = elapsed.as_nanos() as u64;
+            self.latency_sum.fetch_add(nanos, Ordering::Relaxed);
+            self.latency_buckets[bucket_of(elapsed)].fetch_add(1, Ordering::Relaxed);
+        }
+
+        if let Some(kind) = error {
+            let mut errors = self.errors.lock().unwrap_or_else(|e| e.into_inner());
+            match errors.iter_mut().find(|(k, _)| *k == kind) {
+                Some((_, count)) => *count += 1,
+                None => errors.push((kind, 1)),
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        {
+            metrics::counter!("fs_tracing.calls", "operation" => self.name).increment(1);
+            if let Some(elapsed) = elapsed {
+                metrics::histogram!("fs_tracing.latency_seconds", "operation" => self.name)
+                    .record(elapsed.as_secs_f64());
+            }
+            if let Some(kind) = error {
+                metrics::counter!(
+                    "fs_tracing.errors",
+                    "operation" => self.name,
+                    "kind" => format!("{:?}", kind)
+                )
+                .increment(1);
+            }
+        }
+    }
+
+    /// Records the number of bytes read by a successful call returning `value`.
+    pub(crate) fn read<T>(&self, value: &T, bytes: impl FnOnce(&T) -> u64) {
+        if !is_enabled() {
+            return;
+        }
+        let bytes = bytes(value);
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+
+        #[cfg(feature = "metrics")]
+        metrics::counter!("fs_tracing.bytes_read", "operation" => self.name).increment(bytes);
+    }
+
+    /// Records the number of bytes written by a successful call returning `value`.
+    pub(crate) fn written<T>(&self, value: &T, bytes: impl FnOnce(&T) -> u64) {
+        if !is_enabled() {
+            return;
+        }
+        let bytes = bytes(value);
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+
+        #[cfg(feature = "metrics")]
+        metrics::counter!("fs_tracing.bytes_written", "operation" => self.name).increment(bytes);
+    }
+
+    fn merge_into(&self, stats: &mut OperationStats) {
+        stats.calls += self.calls.load(Ordering::Relaxed);
+        stats.bytes_read += self.bytes_read.load(Ordering::Relaxed);
+        stats.bytes_written += self.bytes_written.load(Ordering::Relaxed);
+        stats.latency.sum += Duration::from_nanos(self.latency_sum.load(Ordering::Relaxed));
+        for (merged, bucket) in stats.latency.buckets.iter_mut().zip(&self.latency_buckets) {
+            *merged += bucket.load(Ordering::Relaxed);
+        }
+
+        let errors = self.errors.lock().unwrap_or_else(|e| e.into_inner());
+        for &(kind, count) in errors.iter() {
+            *stats.errors.entry(kind).or_insert(0) += count;
+        }
+    }
+
+    fn reset(&self) {
+        self.calls.store(0, Ordering::Relaxed);
+        self.bytes_read.store(0, Ordering::Relaxed);
+        self.bytes_written.store(0, Ordering::Relaxed);
+        self.latency_sum.store(0, Ordering::Relaxed);
+        for bucket in &self.latency_buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.errors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+}
+
+/// Bucket `i` counts latencies up to 2^`i` microseconds, except for the last one which has no
+/// upper bound.
+fn bucket_of(elapsed: Duration) -> usize {
+    let micros = elapsed.as_micros() as u64;
+    let bucket = (64 - micros.saturating_sub(1).leading_zeros()) as usize;
+    bucket.min(BUCKETS - 1)
+}
+
+fn upper_bound_of(bucket: usize) -> Option<Duration> {
+    if bucket + 1 < BUCKETS {
+        Some(Duration::from_micros(1 << bucket))
+    } else {
+        None
+    }
+}
+
+/// Enables or disables collecting the statistics.
+///
+/// The statistics collected so far are kept when disabling, until [`reset`].
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether the statistics are collected.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Takes a snapshot of the statistics collected so far.
+pub fn snapshot() -> Snapshot {
+    let mut operations = BTreeMap::new();
+    for callsite in callsites().iter() {
+        callsite.merge_into(
+            operations
+                .entry(callsite.name)
+                .or_insert_with(OperationStats::new),
+        );
+    }
+
+    Snapshot { operations }
+}
+
+/// Resets all the statistics to zero.
+///
+/// Calls running concurrently with `reset` may or may not be counted.
+pub fn reset() {
+    for callsite in callsites().iter() {
+        callsite.reset();
+    }
+}
+
+/// Statistics of all the operations which have been called at least once.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    operations: BTreeMap<&'static str, OperationStats>,
+}
+
+impl Snapshot {
+    /// Returns the statistics of the operation named `operation`, such as `read` or
+    /// `File::write_all`.
+    pub fn get(&self, operation: &str) -> Option<&OperationStats> {
+        self.operations.get(operation)
+    }
+
+    /// Returns an iterator over the operations and their statistics, sorted by the names.
+    pub fn operations(&self) -> impl Iterator<Item = (&'static str, &OperationStats)> + '_ {
+        self.operations.iter().map(|(name, stats)| (*name, stats))
+    }
+}
+
+/// Statistics of a single operation.
+#[derive(Debug, Clone)]
+pub struct OperationStats {
+    calls: u64,
+    errors: HashMap<io::ErrorKind, u64>,
+    bytes_read: u64,
+    bytes_written: u64,
+    latency: Histogram,
+}
+
+impl OperationStats {
+    fn new() -> Self {
+        Self {
+            calls: 0,
+            errors: HashMap::new(),
+            bytes_read: 0,
+            bytes_written: 0,
+            latency: Histogram {
+                sum: Duration::from_secs(0),
+                buckets: [0; BUCKETS],
+            },
+        }
+    }
+
+    /// Returns the number of calls, including failed ones.
+    pub fn calls(&self) -> u64 {
+        self.calls
+    }
+
+    /// Returns the number of failed calls.
+    pub fn errors(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    /// Returns the number of calls failed with `kind`.
+    pub fn errors_of(&self, kind: io::ErrorKind) -> u64 {
+        self.errors.get(&kind).copied().unwrap_or(0)
+    }
+
+    /// Returns the number of failed calls by their kinds.
+    pub fn errors_by_kind(&self) -> &HashMap<io::ErrorKind, u64> {
+        &self.errors
+    }
+
+    /// Returns the number of bytes read by successful calls.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the number of bytes written by successful calls.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Returns the histogram of the latency of the calls.
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+}
+
+/// Latency histogram with exponential buckets from 1µs to about 4s.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    sum: Duration,
+    buckets: [u64; BUCKETS],
+}
+
+impl Histogram {
+    /// Returns the number of recorded samples.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns the sum of the recorded samples.
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// Returns the mean of the recorded samples, or `None` if there are none.
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(Duration::from_nanos(
+                (self.sum.as_nanos() / u128::from(count)) as u64,
+            )),
+        }
+    }
+
+    /// Returns an iterator over the upper bounds of the buckets and the number of samples in them.
+    ///
+    /// The last bucket has no upper bound.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(bucket, count)| (upper_bound_of(bucket), *count))
+    }
+
+    /// Returns the upper bound of the bucket containing the `q`-quantile (`0.0 <= q <= 1.0`).
+    ///
+    /// Returns `None` if there are no samples or the quantile falls in the last bucket.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, bucket_count) in self.buckets.iter().enumerate() {
+            seen += bucket_count;
+            if seen >= rank {
+                return upper_bound_of(bucket);
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{DirBuilder, File, OpenOptions};
+use std::{
+    env, fmt, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time,
+};
+use tracing::{debug, warn};
+
+/// Distinguishes the temporary paths created by a process.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of names tried before giving up on creating a temporary path.
+const ATTEMPTS: usize = 16;
+
+/// Creates a new path in `dir` with `create`, retrying with another name if it already exists.
+fn create_unique<T>(
+    dir: &Path,
+    create: impl Fn(&Path) -> io::Result<T>,
+) -> io::Result<(T, PathBuf)> {
+    let nanos = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.subsec_nanos());
+
+    let mut last = None;
+    for _ in 0..ATTEMPTS {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!(".tmp{}.{}.{}", process::id(), nanos, id));
+        match create(&path) {
+            Ok(value) => return Ok((value, path)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => last = Some(e),
+            Err(e) => return Err(e),
+        }
+    }
+
+    // the last error is already traced.
+    Err(last.expect("at least one name is tried"))
+}
+
+// the names are predictable, so the paths are private to the owner regardless of the umask.
+fn create_file(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+fn create_dir(path: &Path) -> io::Result<()> {
+    let mut builder = DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(path)
+}
+
+/// Creates an anonymous temporary file in [`env::temp_dir`].
+///
+/// On Unix, the file is removed right after being created, so that it disappears once closed. On
+/// other platforms, it cannot be removed while open and is left in the temporary directory; use
+/// [`NamedTempFile`] to remove it on drop.
+pub fn tempfile() -> io::Result<File> {
+    let (file, path) = create_unique(&env::temp_dir(), create_file)?;
+    debug!(target: "fs_tracing", ?path, "created temporary file");
+
+    #[cfg(unix)]
+    crate::remove_file(&path)?;
+
+    Ok(file)
+}
+
+/// A temporary file which is removed when dropped.
+///
+/// A failure to remove the file is reported as a `WARN` event with the path.
+pub struct NamedTempFile {
+    file: File,
+    // `None` once closed or kept.
+    path: Option<PathBuf>,
+}
+
+impl fmt::Debug for NamedTempFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NamedTempFile")
+            .field("file", &self.file)
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl NamedTempFile {
+    /// Creates a temporary file in [`env::temp_dir`].
+    pub fn new() -> io::Result<Self> {
+        Self::new_in(env::temp_dir())
+    }
+
+    /// Creates a temporary file in `dir`, readable and writable only by its owner on Unix.
+    pub fn new_in<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let (file, path) = create_unique(dir.as_ref(), create_file)?;
+        debug!(target: "fs_tracing", ?path, "created temporary file");
+        Ok(Self {
+            file,
+            path: Some(path),
+        })
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        self.path
+            .as_deref()
+            .expect("the path is kept until dropped")
+    }
+
+    /// Returns the opened file.
+    pub fn as_file(&self) -> &File {
+        &self.file
+    }
+
+    /// Returns the opened file mutably.
+    pub fn as_file_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Removes the file, returning the error instead of reporting it.
+    pub fn close(mut self) -> io::Result<()> {
+        match self.path.take() {
+            Some(path) => remove(&path, "temporary file", |path| crate::remove_file(path)),
+            None => Ok(()),
+        }
+    }
+
+    /// Keeps the file instead of removing it, returning its path.
+    pub fn keep(mut self) -> PathBuf {
+        self.path.take().expect("the path is kept until dropped")
+    }
+}
+
+impl Drop for NamedTempFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            remove_or_warn(&path, "temporary file", |path| crate::remove_file(path));
+        }
+    }
+}
+
+/// A temporary directory which is removed with its contents when dropped.
+///
+/// A failure to remove the directory is reported as a `WARN` event with the path.
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// let dir = fs_tracing::TempDir::new()?;
+/// fs_tracing::write(dir.path().join("data"), "data")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TempDir {
+    // `None` once closed or kept.
+    path: Option<PathBuf>,
+}
+
+impl TempDir {
+    /// Creates a temporary directory in [`env::temp_dir`].
+    pub fn new() -> io::Result<Self> {
+        Self::new_in(env::temp_dir())
+    }
+
+    /// Creates a temporary directory in `dir`, accessible only by its owner on Unix.
+    pub fn new_in<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let ((), path) = create_unique(dir.as_ref(), create_dir)?;
+        debug!(target: "fs_tracing", ?path, "created temporary directory");
+        Ok(Self { path: Some(path) })
+    }
+
+    /// Returns the path of the directory.
+    pub fn path(&self) -> &Path {
+        self.path
+            .as_deref()
+            .expect("the path is kept until dropped")
+    }
+
+    /// Removes the directory, returning the error instead of reporting it.
+    pub fn close(mut self) -> io::Result<()> {
+        match self.path.take() {
+            Some(path) => remove(&path, "temporary directory", |path| {
+                crate::remove_dir_all(path)
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Keeps the directory instead of removing it, returning its path.
+    pub fn keep(mut self) -> PathBuf {
+        self.path.take().expect("the path is kept until dropped")
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            remove_or_warn(&path, "temporary directory", |path| {
+                crate::remove_dir_all(path)
+            });
+        }
+    }
+}
+
+fn remove(path: &Path, what: &str, remove_path: fn(&Path) -> io::Result<()>) -> io::Result<()> {
+    remove_path(path)?;
+    debug!(target: "fs_tracing", ?path, "removed {}", what);
+    Ok(())
+}
+
+fn remove_or_warn(path: &Path, what: &str, remove_path: fn(&Path) -> io::Result<()>) {
+    if let Err(e) = remove(path, what, remove_path) {
+        // the error is wrapped, so it also prints the trace of the removal.
+        warn!(target: "fs_tracing", ?path, error = %e, "failed to remove {}", what);
+    }
+}
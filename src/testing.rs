@@ -0,0 +1,197 @@
+//! Helpers for asserting on the context of the returned errors in tests.
+//!
+//! [`set_default`] installs a subscriber with an `ErrorLayer` for the current thread until the
+//! returned guard is dropped, so that each test can capture error contexts without a global
+//! subscriber. [`Context::of`] then gives the structured context of an error returned under that
+//! subscriber: its kind, the operation which failed, and the fields recorded for it.
+//!
+//! ```
+//! use fs_tracing::testing;
+//! use std::io::ErrorKind;
+//!
+//! let _guard = testing::set_default();
+//!
+//! let e = fs_tracing::copy("/not_exist", "/x").unwrap_err();
+//! fs_tracing::assert_error!(e, "copy", ErrorKind::NotFound, from = "/not_exist", to = "/x");
+//!
+//! let context = testing::Context::of(&e).unwrap();
+//! assert_eq!(context.operation(), Some("copy"));
+//! assert_eq!(context.field("to"), Some(r#""/x""#));
+//! ```
+//!
+//! The field values are formatted with [`Debug`](std::fmt::Debug), as in the spans. They are only
+//! available for the errors captured under the subscriber of this module, since other subscribers
+//! format the fields for humans rather than for parsing.
+
+use std::{fmt, io};
+use tracing::{field::Field, subscriber::DefaultGuard, Metadata, Subscriber};
+use tracing_error::ErrorLayer;
+use tracing_subscriber::{
+    fmt::format::{debug_fn, FieldFn},
+    layer::SubscriberExt,
+    Registry,
+};
+
+/// Separates the formatted fields. Control characters are escaped by `Debug`, so that it never
+/// appears in a value.
+const SEPARATOR: char = '\u{1f}';
+
+type FormatFields = FieldFn<fn(&mut dyn fmt::Write, &Field, &dyn fmt::Debug) -> fmt::Result>;
+
+fn format_field(writer: &mut dyn fmt::Write, field: &Field, value: &dyn fmt::Debug) -> fmt::Result {
+    write!(writer, "{}={:?}{}", field.name(), value, SEPARATOR)
+}
+
+/// Returns a subscriber capturing the error contexts in a parsable format.
+pub fn subscriber() -> impl Subscriber + Send + Sync + 'static {
+    let format: FormatFields = debug_fn(format_field);
+    Registry::default().with(ErrorLayer::new(format))
+}
+
+/// Installs [`subscriber`] for the current thread until the returned guard is dropped.
+pub fn set_default() -> DefaultGuard {
+    tracing::subscriber::set_default(subscriber())
+}
+
+/// A span captured in the context of an error.
+#[derive(Debug, Clone)]
+pub struct Span {
+    metadata: &'static Metadata<'static>,
+    fields: Vec<(String, String)>,
+}
+
+impl Span {
+    /// Returns the name of the span, which is the operation for the spans of fs-tracing.
+    pub fn name(&self) -> &'static str {
+        self.metadata.name()
+    }
+
+    /// Returns the target of the span, which is `fs_tracing` for the spans of fs-tracing.
+    pub fn target(&self) -> &'static str {
+        self.metadata.target()
+    }
+
+    /// Returns the `Debug` representation of the field `name`, if recorded.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the recorded fields as pairs of names and `Debug` representations.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+/// The structured context of an error returned by fs-tracing.
+#[derive(Debug, Clone)]
+pub struct Context {
+    kind: io::ErrorKind,
+    spans: Vec<Span>,
+}
+
+impl Context {
+    /// Returns the context of `error`, or `None` if it was not returned by fs-tracing.
+    pub fn of(error: &io::Error) -> Option<Self> {
+        let mut inner = error.get_ref()?.downcast_ref::<crate::error::Error>()?;
+        // an error wrapped again by `IoResultExt` has the previous one as its source, whose spans
+        // come first.
+        let mut nested = vec![inner];
+        while let Some(source) = std::error::Error::source(inner)
+            .and_then(|source| source.downcast_ref::<crate::error::Error>())
+        {
+            nested.push(source);
+            inner = source;
+        }
+
+        let mut spans = Vec::new();
+        for inner in nested.iter().rev() {
+            inner.span_trace().with_spans(|metadata, fields| {
+                spans.push(Span {
+                    metadata,
+                    fields: parse_fields(fields),
+                });
+                true
+            });
+        }
+
+        Some(Self {
+            kind: error.kind(),
+            spans,
+        })
+    }
+
+    /// Returns the kind of the error.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.kind
+    }
+
+    /// Returns the name of the operation which failed, such as `copy` or `File::write_all`.
+    pub fn operation(&self) -> Option<&'static str> {
+        self.operation_span().map(Span::name)
+    }
+
+    /// Returns the `Debug` representation of the field `name` of the operation which failed.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.operation_span()?.field(name)
+    }
+
+    /// Returns the captured spans, from the innermost one.
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    fn operation_span(&self) -> Option<&Span> {
+        self.spans.iter().find(|span| span.target() == "fs_tracing")
+    }
+}
+
+fn parse_fields(fields: &str) -> Vec<(String, String)> {
+    fields
+        .split(SEPARATOR)
+        // `add_fields` separates the fields recorded later with a space.
+        .map(str::trim_start)
+        .filter_map(|field| field.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Asserts that an `io::Error` was returned by fs-tracing for the given operation, with the given
+/// kind and fields.
+///
+/// The fields are compared by their `Debug` representations, so that `to = "/x"` matches a path
+/// `/x`.
+///
+/// ```
+/// use std::io::ErrorKind;
+///
+/// let _guard = fs_tracing::testing::set_default();
+///
+/// let e = fs_tracing::read("/not_exist").unwrap_err();
+/// fs_tracing::assert_error!(e, "read", ErrorKind::NotFound, path = "/not_exist");
+/// ```
+#[macro_export]
+macro_rules! assert_error {
+    ($error:expr, $operation:expr, $kind:expr $(, $field:ident = $value:expr)* $(,)?) => {{
+        let error: &std::io::Error = &$error;
+        let context = match $crate::testing::Context::of(error) {
+            Some(context) => context,
+            None => panic!("the error has no fs-tracing context: {:?}", error),
+        };
+        assert_eq!(context.operation(), Some($operation), "operation of {:?}", context);
+        assert_eq!(context.kind(), $kind, "kind of {:?}", context);
+        $(
+            assert_eq!(
+                context.field(stringify!($field)),
+                Some(format!("{:?}", $value).as_str()),
+                "field `{}` of {:?}",
+                stringify!($field),
+                context,
+            );
+        )*
+    }};
+}
@@ -0,0 +1,92 @@
+use crate::error::Error;
+use std::{io, path::Path};
+use tracing::info_span;
+
+/// Returns the 1-based line and column of the byte at `offset` in `source`, counting the columns
+/// in characters.
+#[cfg(feature = "toml")]
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = source.get(..offset).unwrap_or(source);
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+/// Reads the file at `path` and deserializes its contents as JSON.
+///
+/// Reading the file fails in the `read` span. A parse error has the kind
+/// [`InvalidData`](io::ErrorKind::InvalidData) and is traced in a `read_json` span with the path
+/// and the `line` and `column` where parsing failed.
+#[cfg(feature = "json")]
+pub fn read_json<T: serde::de::DeserializeOwned, P: AsRef<Path>>(path: P) -> io::Result<T> {
+    fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> io::Result<T> {
+        let contents = crate::read(path)?;
+        serde_json::from_slice(&contents).map_err(|e| {
+            let (line, column) = (e.line(), e.column());
+            info_span!(target: "fs_tracing", "read_json", ?path, line, column)
+                .in_scope(|| Error::wrap_std(io::Error::new(io::ErrorKind::InvalidData, e)))
+        })
+    }
+
+    read_json(path.as_ref())
+}
+
+/// Reads the file at `path` and deserializes its contents as TOML.
+///
+/// Reading the file fails in the `read_to_string` span. A parse error has the kind
+/// [`InvalidData`](io::ErrorKind::InvalidData) and is traced in a `read_toml` span with the path
+/// and, when known, the `line` and `column` where parsing failed.
+#[cfg(feature = "toml")]
+pub fn read_toml<T: serde::de::DeserializeOwned, P: AsRef<Path>>(path: P) -> io::Result<T> {
+    fn read_toml<T: serde::de::DeserializeOwned>(path: &Path) -> io::Result<T> {
+        let contents = crate::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| {
+            let (line, column) = match e.span() {
+                Some(span) => {
+                    let (line, column) = position(&contents, span.start);
+                    (Some(line), Some(column))
+                }
+                None => (None, None),
+            };
+            info_span!(target: "fs_tracing", "read_toml", ?path, line, column)
+                .in_scope(|| Error::wrap_std(io::Error::new(io::ErrorKind::InvalidData, e)))
+        })
+    }
+
+    read_toml(path.as_ref())
+}
+
+/// Serializes `value` as pretty-printed JSON and writes it to the file at `path`.
+///
+/// A serialization error has the kind [`InvalidData`](io::ErrorKind::InvalidData) and is traced
+/// in a `write_json` span with the path. Writing the file fails in the `write` span.
+#[cfg(feature = "json")]
+pub fn write_json<T: serde::Serialize + ?Sized, P: AsRef<Path>>(
+    path: P,
+    value: &T,
+) -> io::Result<()> {
+    fn write_json<T: serde::Serialize + ?Sized>(path: &Path, value: &T) -> io::Result<()> {
+        let contents = serde_json::to_vec_pretty(value).map_err(|e| {
+            info_span!(target: "fs_tracing", "write_json", ?path)
+                .in_scope(|| Error::wrap_std(io::Error::new(io::ErrorKind::InvalidData, e)))
+        })?;
+        crate::write(path, contents)
+    }
+
+    write_json(path.as_ref(), value)
+}
+
+#[cfg(all(test, feature = "toml"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_are_counted_in_characters() {
+        let source = "name = \"caf\u{e9}\"\nk\u{e9}y = [1, \u{1f600}]\n";
+        assert_eq!(position(source, 0), (1, 1));
+        assert_eq!(position(source, source.find('\n').unwrap() + 1), (2, 1));
+        assert_eq!(position(source, source.find('\u{1f600}').unwrap()), (2, 11));
+        assert_eq!(position(source, source.len()), (3, 1));
+    }
+}
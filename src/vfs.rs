@@ -0,0 +1,649 @@
+//! Filesystem abstraction with real and in-memory backends.
+//!
+//! [`FileSystem`] covers the commonly used part of the wrapped API. [`OsFs`] forwards to the
+//! wrappers in the crate root, while [`MemoryFs`] keeps a hermetic filesystem in memory. Both
+//! return errors with the same operation names and fields in their span traces, so code written
+//! against `FileSystem` can be unit tested on `MemoryFs` including its error context.
+//!
+//! `MemoryFs` is hermetic: its operations are not [audited](crate::audit), checked against the
+//! [policy](crate::policy) or the [faults](crate::fault), skipped in [dry-run
+//! mode](crate::dry_run), [recorded or replayed](crate::replay), nor counted in the
+//! [statistics](crate::stats), all of which are about the real filesystem.
+//!
+//! ```
+//! use fs_tracing::vfs::{FileSystem, MemoryFs};
+//! use std::{io::ErrorKind, path::Path};
+//!
+//! fn load_config(fs: &dyn FileSystem) -> std::io::Result<String> {
+//!     fs.read_to_string(Path::new("/etc/app.conf"))
+//! }
+//!
+//! let fs: Box<dyn FileSystem> = Box::new(MemoryFs::new());
+//! assert_eq!(load_config(&*fs).unwrap_err().kind(), ErrorKind::NotFound);
+//!
+//! fs.create_dir_all(Path::new("/etc")).unwrap();
+//! fs.write(Path::new("/etc/app.conf"), b"verbose = true").unwrap();
+//! assert_eq!(load_config(&*fs).unwrap(), "verbose = true");
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, Read, Seek, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// A file opened on a [`FileSystem`].
+pub trait VfsFile: Read + Write + Seek + fmt::Debug + Send {}
+
+impl<T: Read + Write + Seek + fmt::Debug + Send> VfsFile for T {}
+
+/// A filesystem on which the wrapped operations can be performed.
+///
+/// The trait is object safe, so that the backend can be chosen at runtime as a
+/// `Box<dyn FileSystem>`.
+pub trait FileSystem {
+    /// Opens a file in read-only mode, like [`File::open`](crate::File::open).
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
+
+    /// Opens a file in write-only mode, creating or truncating it, like
+    /// [`File::create`](crate::File::create).
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
+
+    /// Reads the contents of a file, like [`read`](crate::read).
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Reads the contents of a file as a string, like [`read_to_string`](crate::read_to_string).
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+
+    /// Writes the contents of a file, like [`write`](crate::write).
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Queries the metadata of a path, following symbolic links, like
+    /// [`metadata`](crate::metadata).
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Returns the paths of the entries in a directory, which are `path` joined with their names,
+    /// like [`read_dir`](crate::read_dir).
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Creates a directory, like [`create_dir`](crate::create_dir).
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Creates a directory and its missing ancestors, like
+    /// [`create_dir_all`](crate::create_dir_all).
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes a file, like [`remove_file`](crate::remove_file).
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes an empty directory, like [`remove_dir`](crate::remove_dir).
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes a directory and its contents, like [`remove_dir_all`](crate::remove_dir_all).
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Renames a file or a directory, like [`rename`](crate::rename).
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Copies the contents of a file, like [`copy`](crate::copy).
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64>;
+}
+
+/// A backend-independent subset of [`Metadata`](crate::Metadata).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    is_dir: bool,
+    len: u64,
+}
+
+impl Metadata {
+    /// Returns whether the path is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    /// Returns whether the path is a regular file.
+    pub fn is_file(&self) -> bool {
+        !self.is_dir
+    }
+
+    /// Returns the size of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+/// The real filesystem of the OS.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsFs;
+
+impl FileSystem for OsFs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(crate::File::open(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(crate::File::create(path)?))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        crate::read(path)
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        crate::read_to_string(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        crate::write(path, contents)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        crate::metadata(path).map(|metadata| Metadata {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        crate::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        crate::create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        crate::create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        crate::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        crate::remove_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        crate::remove_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        crate::rename(from, to)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        crate::copy(from, to)
+    }
+}
+
+type Contents = Arc<Mutex<Vec<u8>>>;
+
+fn lock(contents: &Contents) -> MutexGuard<'_, Vec<u8>> {
+    contents.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[derive(Clone)]
+enum Node {
+    File(Contents),
+    Dir,
+}
+
+/// The entries of an in-memory filesystem keyed by their normalized absolute paths.
+struct Tree {
+    nodes: BTreeMap<PathBuf, Node>,
+}
+
+fn error(kind: io::ErrorKind) -> io::Error {
+    io::Error::from(kind)
+}
+
+/// Resolves `path` against the root, removing `.` and `..` components.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(name) => normalized.push(name),
+        }
+    }
+    normalized
+}
+
+impl Tree {
+    fn node(&self, path: &Path) -> io::Result<&Node> {
+        self.nodes
+            .get(path)
+            .ok_or_else(|| error(io::ErrorKind::NotFound))
+    }
+
+    fn file(&self, path: &Path) -> io::Result<Contents> {
+        match self.node(path)? {
+            Node::File(contents) => Ok(contents.clone()),
+            Node::Dir => Err(error(io::ErrorKind::IsADirectory)),
+        }
+    }
+
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) => match self.node(parent)? {
+                Node::Dir => Ok(()),
+                Node::File(_) => Err(error(io::ErrorKind::NotADirectory)),
+            },
+            // the root always exists.
+            None => Err(error(io::ErrorKind::AlreadyExists)),
+        }
+    }
+
+    fn has_children(&self, path: &Path) -> bool {
+        self.nodes.keys().any(|p| p != path && p.starts_with(path))
+    }
+
+    fn create(&mut self, path: &Path) -> io::Result<Contents> {
+        match self.nodes.get(path) {
+            Some(Node::File(contents)) => {
+                lock(contents).clear();
+                Ok(contents.clone())
+            }
+            Some(Node::Dir) => Err(error(io::ErrorKind::IsADirectory)),
+            None => {
+                self.check_parent(path)?;
+                let contents = Contents::default();
+                self.nodes
+                    .insert(path.to_path_buf(), Node::File(contents.clone()));
+                Ok(contents)
+            }
+        }
+    }
+
+    /// Returns the names of the entries of the directory `path`.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        match self.node(path)? {
+            Node::Dir => Ok(self
+                .nodes
+                .keys()
+                .filter(|p| p.parent() == Some(path))
+                .filter_map(|p| p.file_name().map(PathBuf::from))
+                .collect()),
+            Node::File(_) => Err(error(io::ErrorKind::NotADirectory)),
+        }
+    }
+
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        if self.nodes.contains_key(path) {
+            return Err(error(io::ErrorKind::AlreadyExists));
+        }
+        self.check_parent(path)?;
+        self.nodes.insert(path.to_path_buf(), Node::Dir);
+        Ok(())
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        match self.nodes.get(path) {
+            Some(Node::Dir) => Ok(()),
+            Some(Node::File(_)) => Err(error(io::ErrorKind::AlreadyExists)),
+            None => {
+                if let Some(parent) = path.parent() {
+                    self.create_dir_all(parent)?;
+                }
+                self.nodes.insert(path.to_path_buf(), Node::Dir);
+                Ok(())
+            }
+        }
+    }
+
+    fn remove_file(&mut self, path: &Path) -> io::Result<()> {
+        self.file(path)?;
+        self.nodes.remove(path);
+        Ok(())
+    }
+
+    fn remove_dir(&mut self, path: &Path) -> io::Result<()> {
+        match self.node(path)? {
+            Node::File(_) => Err(error(io::ErrorKind::NotADirectory)),
+            Node::Dir if path.parent().is_none() => Err(error(io::ErrorKind::PermissionDenied)),
+            Node::Dir if self.has_children(path) => Err(error(io::ErrorKind::DirectoryNotEmpty)),
+            Node::Dir => {
+                self.nodes.remove(path);
+                Ok(())
+            }
+        }
+    }
+
+    fn remove_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        match self.node(path)? {
+            Node::File(_) => Err(error(io::ErrorKind::NotADirectory)),
+            Node::Dir if path.parent().is_none() => Err(error(io::ErrorKind::PermissionDenied)),
+            Node::Dir => {
+                self.nodes.retain(|p, _| !p.starts_with(path));
+                Ok(())
+            }
+        }
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        let node = self.node(from)?.clone();
+        if from == to {
+            return Ok(());
+        }
+        if to.starts_with(from) {
+            return Err(error(io::ErrorKind::InvalidInput));
+        }
+        self.check_parent(to)?;
+
+        match (&node, self.nodes.get(to)) {
+            (Node::File(_), Some(Node::Dir)) => return Err(error(io::ErrorKind::IsADirectory)),
+            (Node::Dir, Some(Node::File(_))) => return Err(error(io::ErrorKind::NotADirectory)),
+            (Node::Dir, Some(Node::Dir)) if self.has_children(to) => {
+                return Err(error(io::ErrorKind::DirectoryNotEmpty))
+            }
+            _ => {}
+        }
+
+        let moved: Vec<(PathBuf, Node)> = self
+            .nodes
+            .iter()
+            .filter(|(p, _)| p.starts_with(from))
+            .map(|(p, node)| (p.clone(), node.clone()))
+            .collect();
+        for (p, _) in &moved {
+            self.nodes.remove(p);
+        }
+        for (p, node) in moved {
+            let path = match p.strip_prefix(from) {
+                Ok(suffix) if suffix != Path::new("") => to.join(suffix),
+                _ => to.to_path_buf(),
+            };
+            self.nodes.insert(path, node);
+        }
+        Ok(())
+    }
+
+    fn copy(&mut self, from: &Path, to: &Path) -> io::Result<u64> {
+        let data = lock(&self.file(from)?).clone();
+        let contents = self.create(to)?;
+        let mut contents = lock(&contents);
+        *contents = data;
+        Ok(contents.len() as u64)
+    }
+}
+
+/// A hermetic filesystem kept in memory.
+///
+/// Paths are resolved against the root `/` of the in-memory filesystem, which always exists.
+/// Symbolic links and permissions are not supported. Clones share the same filesystem.
+#[derive(Clone)]
+pub struct MemoryFs {
+    tree: Arc<Mutex<Tree>>,
+}
+
+impl fmt::Debug for MemoryFs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.tree().nodes.keys()).finish()
+    }
+}
+
+impl MemoryFs {
+    /// Creates an empty filesystem with only the root directory.
+    pub fn new() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(PathBuf::from("/"), Node::Dir);
+        Self {
+            tree: Arc::new(Mutex::new(Tree { nodes })),
+        }
+    }
+
+    fn tree(&self) -> MutexGuard<'_, Tree> {
+        self.tree.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn open_file(&self, path: &Path, write: bool) -> io::Result<MemoryFile> {
+        let normalized = normalize(path);
+        let contents = if write {
+            self.tree().create(&normalized)?
+        } else {
+            self.tree().file(&normalized)?
+        };
+
+        Ok(MemoryFile {
+            path: path.to_path_buf(),
+            cursor: Cursor {
+                contents,
+                position: 0,
+                read: !write,
+                write,
+            },
+        })
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let file = traced!(span_only "File::open", self.open_file(path, false), ?path)?;
+        Ok(Box::new(file))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let file = traced!(span_only "File::create", self.open_file(path, true), ?path)?;
+        Ok(Box::new(file))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        traced!(
+            span_only "read",
+            self.tree()
+                .file(&normalize(path))
+                .map(|contents| lock(&contents).clone()),
+            ?path
+        )
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        traced!(
+            span_only "read_to_string",
+            self.tree().file(&normalize(path)).and_then(|contents| {
+                String::from_utf8(lock(&contents).clone())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }),
+            ?path
+        )
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        traced!(
+            span_only "write",
+            self.tree()
+                .create(&normalize(path))
+                .map(|file| lock(&file).extend_from_slice(contents)),
+            ?path,
+            len = contents.len()
+        )
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        traced!(
+            span_only "metadata",
+            self.tree()
+                .node(&normalize(path))
+                .map(|node| match node {
+                    Node::File(contents) => Metadata {
+                        is_dir: false,
+                        len: lock(contents).len() as u64,
+                    },
+                    Node::Dir => Metadata {
+                        is_dir: true,
+                        len: 0,
+                    },
+                }),
+            ?path
+        )
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        traced!(span_only "read_dir", self.tree().read_dir(&normalize(path)), ?path).map(|names| {
+            // like the real filesystem, the entries are joined to the given path.
+            names.into_iter().map(|name| path.join(name)).collect()
+        })
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        traced!(span_only "create_dir", self.tree().create_dir(&normalize(path)), ?path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        traced!(span_only "create_dir_all", self.tree().create_dir_all(&normalize(path)), ?path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        traced!(span_only "remove_file", self.tree().remove_file(&normalize(path)), ?path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        traced!(span_only "remove_dir", self.tree().remove_dir(&normalize(path)), ?path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        traced!(span_only "remove_dir_all", self.tree().remove_dir_all(&normalize(path)), ?path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        traced!(
+            span_only "rename",
+            self.tree().rename(&normalize(from), &normalize(to)),
+            ?from,
+            ?to
+        )
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        traced!(span_only "copy", self.tree().copy(&normalize(from), &normalize(to)), ?from, ?to)
+    }
+}
+
+/// A file opened on a [`MemoryFs`].
+///
+/// The file keeps its contents alive even if it is removed from the filesystem.
+pub struct MemoryFile {
+    path: PathBuf,
+    // kept apart from `path` so that the wrapped calls can borrow both.
+    cursor: Cursor,
+}
+
+struct Cursor {
+    contents: Contents,
+    position: u64,
+    read: bool,
+    write: bool,
+}
+
+impl fmt::Debug for MemoryFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryFile")
+            .field("path", &self.path)
+            .field("read", &self.cursor.read)
+            .field("write", &self.cursor.write)
+            .finish()
+    }
+}
+
+impl Cursor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.read {
+            return Err(error(io::ErrorKind::PermissionDenied));
+        }
+
+        let contents = lock(&self.contents);
+        let start = (self.position as usize).min(contents.len());
+        let len = buf.len().min(contents.len() - start);
+        buf[..len].copy_from_slice(&contents[start..start + len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.write {
+            return Err(error(io::ErrorKind::PermissionDenied));
+        }
+
+        let mut contents = lock(&self.contents);
+        let start = self.position as usize;
+        if contents.len() < start + buf.len() {
+            contents.resize(start + buf.len(), 0);
+        }
+        contents[start..start + buf.len()].copy_from_slice(buf);
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            io::SeekFrom::Start(offset) => (0, offset as i64),
+            io::SeekFrom::End(offset) => (lock(&self.contents).len() as i64, offset),
+            io::SeekFrom::Current(offset) => (self.position as i64, offset),
+        };
+        match base.checked_add(offset) {
+            Some(position) if position >= 0 => {
+                self.position = position as u64;
+                Ok(self.position)
+            }
+            _ => Err(error(io::ErrorKind::InvalidInput)),
+        }
+    }
+}
+
+impl Read for MemoryFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        traced!(span_only "File::read", self.cursor.read(buf), ?self, len = buf.len())
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        traced!(span_only "File::write", self.cursor.write(buf), ?self, len = buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryFile {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        traced!(span_only "File::seek", self.cursor.seek(pos), ?self, ?pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(fs: &dyn FileSystem, dir: &Path) -> Vec<PathBuf> {
+        fs.create_dir_all(&dir.join("sub")).unwrap();
+        fs.write(&dir.join("file"), b"data").unwrap();
+        let mut entries = fs.read_dir(&dir.join("sub/..")).unwrap();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn read_dir_joins_the_given_path_on_both_backends() {
+        let temp = crate::TempDir::new().unwrap();
+        let backends: [(Box<dyn FileSystem>, &Path); 2] = [
+            (Box::new(OsFs), temp.path()),
+            (Box::new(MemoryFs::new()), Path::new("/data")),
+        ];
+
+        for (fs, dir) in &backends {
+            let given = dir.join("sub/..");
+            assert_eq!(entries(&**fs, dir), [given.join("file"), given.join("sub")]);
+        }
+    }
+}
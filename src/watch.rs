@@ -0,0 +1,250 @@
+use crate::audit::Access;
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use nix::errno::Errno;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, warn};
+
+/// Large enough for a few events with names up to `NAME_MAX`.
+const BUFFER_SIZE: usize = 4096;
+
+/// A change of the filesystem reported by a [`Watcher`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// A file or a directory was created.
+    Create(PathBuf),
+    /// The contents of a file were modified.
+    Modify(PathBuf),
+    /// A file or a directory was removed.
+    Remove(PathBuf),
+    /// A file or a directory was renamed. A side is `None` if it is outside of the watched
+    /// directories.
+    Rename {
+        /// The path before the rename.
+        from: Option<PathBuf>,
+        /// The path after the rename.
+        to: Option<PathBuf>,
+    },
+}
+
+/// Watches paths for changes with inotify.
+///
+/// The watcher is an iterator blocking until the next change of a watched path. Each change is
+/// also reported as a `DEBUG` event, and a failure to set up a watch because of a system limit is
+/// preceded by a `WARN` event telling the `sysctl` setting to raise and its current value.
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// let mut watcher = fs_tracing::Watcher::new()?;
+/// watcher.watch("/etc")?;
+/// for event in watcher {
+///     println!("{:?}", event?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Watcher {
+    inotify: Inotify,
+    paths: HashMap<WatchDescriptor, PathBuf>,
+    buffer: Vec<u8>,
+    pending: VecDeque<WatchEvent>,
+}
+
+impl fmt::Debug for Watcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watcher")
+            .field("paths", &self.paths.values().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Returns the value of the `sysctl` setting `fs.inotify.<name>`.
+fn limit(name: &str) -> Option<u64> {
+    // the limit is only read to explain an error, so reading it is not traced.
+    fs::read_to_string(Path::new("/proc/sys/fs/inotify").join(name))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Explains the errors caused by reaching the `sysctl` limit `fs.inotify.<name>` in a `WARN`
+/// event, and returns them as is.
+fn explain(error: io::Error, errno: Errno, name: &str, what: &str) -> io::Error {
+    if error.raw_os_error() == Some(errno as i32) {
+        let sysctl = format!("fs.inotify.{}", name);
+        let limit = limit(name);
+        warn!(
+            target: "fs_tracing",
+            %error,
+            %sysctl,
+            limit,
+            "the limit of inotify {} may be reached",
+            what
+        );
+    }
+    error
+}
+
+fn read_events(
+    inotify: &mut Inotify,
+    buffer: &mut [u8],
+    paths: &mut HashMap<WatchDescriptor, PathBuf>,
+) -> io::Result<Vec<WatchEvent>> {
+    let mut events = Vec::new();
+    // the renames waiting for their `MOVED_TO` half, by cookie.
+    let mut renames = HashMap::new();
+
+    for event in inotify.read_events_blocking(buffer)? {
+        if event.mask.contains(EventMask::Q_OVERFLOW) {
+            warn!(target: "fs_tracing", "inotify queue overflowed, some changes were dropped");
+            continue;
+        }
+        let watched = match paths.get(&event.wd) {
+            Some(path) => path,
+            None => continue,
+        };
+        if event.mask.contains(EventMask::IGNORED) {
+            paths.remove(&event.wd);
+            continue;
+        }
+
+        let path = match event.name {
+            Some(name) => watched.join(name),
+            None => watched.clone(),
+        };
+        if event.mask.contains(EventMask::CREATE) {
+            events.push(WatchEvent::Create(path));
+        } else if event.mask.contains(EventMask::MODIFY) {
+            events.push(WatchEvent::Modify(path));
+        } else if event
+            .mask
+            .intersects(EventMask::DELETE | EventMask::DELETE_SELF)
+        {
+            events.push(WatchEvent::Remove(path));
+        } else if event
+            .mask
+            .intersects(EventMask::MOVED_FROM | EventMask::MOVE_SELF)
+        {
+            renames.insert(event.cookie, events.len());
+            events.push(WatchEvent::Rename {
+                from: Some(path),
+                to: None,
+            });
+        } else if event.mask.contains(EventMask::MOVED_TO) {
+            match renames
+                .remove(&event.cookie)
+                .and_then(|index| events.get_mut(index))
+            {
+                Some(WatchEvent::Rename { to, .. }) => *to = Some(path),
+                _ => events.push(WatchEvent::Rename {
+                    from: None,
+                    to: Some(path),
+                }),
+            }
+        }
+    }
+
+    Ok(events)
+}
+
+impl Watcher {
+    /// Creates a watcher without watched paths.
+    pub fn new() -> io::Result<Self> {
+        traced!(
+            "Watcher::new",
+            Inotify::init()
+                .map_err(|e| explain(e, Errno::EMFILE, "max_user_instances", "instances"))
+                .map(|inotify| Watcher {
+                    inotify,
+                    paths: HashMap::new(),
+                    buffer: vec![0; BUFFER_SIZE],
+                    pending: VecDeque::new(),
+                })
+        )
+    }
+
+    /// Watches the changes of the file or the directory at `path`. The changes of a directory
+    /// include the ones of its entries, but not of their descendants.
+    pub fn watch<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        fn watch(watcher: &mut Watcher, path: &Path) -> io::Result<()> {
+            let mask = WatchMask::CREATE
+                | WatchMask::MODIFY
+                | WatchMask::DELETE
+                | WatchMask::DELETE_SELF
+                | WatchMask::MOVED_FROM
+                | WatchMask::MOVED_TO
+                | WatchMask::MOVE_SELF;
+            traced!(
+                "Watcher::watch" [Access::Metadata => path],
+                watcher
+                    .inotify
+                    .watches()
+                    .add(path, mask)
+                    .map_err(|e| explain(e, Errno::ENOSPC, "max_user_watches", "watches"))
+                    .map(|wd| {
+                        watcher.paths.insert(wd, path.to_path_buf());
+                    }),
+                ?path
+            )
+        }
+
+        watch(self, path.as_ref())
+    }
+
+    /// Stops watching the changes of `path`.
+    pub fn unwatch<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        fn unwatch(watcher: &mut Watcher, path: &Path) -> io::Result<()> {
+            let wd = watcher
+                .paths
+                .iter()
+                .find(|(_, watched)| *watched == path)
+                .map(|(wd, _)| wd.clone());
+            traced!(
+                "Watcher::unwatch" [Access::Metadata => path],
+                match &wd {
+                    Some(wd) => watcher.inotify.watches().remove(wd.clone()).map(|()| {
+                        watcher.paths.remove(wd);
+                    }),
+                    None => Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} is not watched", path.display()),
+                    )),
+                },
+                ?path
+            )
+        }
+
+        unwatch(self, path.as_ref())
+    }
+
+    /// Blocks until the next change of a watched path.
+    pub fn next_event(&mut self) -> io::Result<WatchEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                debug!(target: "fs_tracing", ?event, "filesystem changed");
+                return Ok(event);
+            }
+
+            // waiting for the changes is not a slow filesystem operation.
+            let events = traced!(
+                untimed "Watcher::read",
+                read_events(&mut self.inotify, &mut self.buffer, &mut self.paths),
+                ?self
+            )?;
+            self.pending.extend(events);
+        }
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = io::Result<WatchEvent>;
+
+    /// Blocks until the next change of a watched path. The iteration never ends.
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_event())
+    }
+}
@@ -0,0 +1,257 @@
+use crate::error::Error;
+use std::{
+    collections::HashMap,
+    fmt, io,
+    panic::Location,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+use tracing::{
+    callsite::{self, Identifier},
+    field::{DebugValue, FieldSet},
+    metadata::Kind,
+    subscriber::Interest,
+    Level, Metadata, Span, Value,
+};
+
+/// The callsite of the spans of an operation with a set of fields, created at a location.
+struct Callsite {
+    metadata: OnceLock<Metadata<'static>>,
+}
+
+impl callsite::Callsite for Callsite {
+    fn set_interest(&self, _interest: Interest) {}
+
+    fn metadata(&self) -> &Metadata<'_> {
+        self.metadata
+            .get()
+            .expect("the metadata is set before registering the callsite")
+    }
+}
+
+type Callsites =
+    HashMap<(&'static str, Vec<&'static str>, &'static Location<'static>), &'static Callsite>;
+
+/// The callsites created so far, which live until the end of the process.
+static CALLSITES: OnceLock<Mutex<Callsites>> = OnceLock::new();
+
+/// Returns the metadata of the spans of `operation` with the fields `names` created at `location`.
+fn metadata(
+    operation: &'static str,
+    names: Vec<&'static str>,
+    location: &'static Location<'static>,
+) -> &'static Metadata<'static> {
+    let mut callsites = CALLSITES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    let callsite = *callsites
+        .entry((operation, names, location))
+        .or_insert_with_key(|(_, names, _)| {
+            let callsite: &'static Callsite = Box::leak(Box::new(Callsite {
+                metadata: OnceLock::new(),
+            }));
+            let names: &'static [&'static str] = Box::leak(names.clone().into_boxed_slice());
+            let _ = callsite.metadata.set(Metadata::new(
+                operation,
+                "fs_tracing",
+                Level::INFO,
+                Some(location.file()),
+                Some(location.line()),
+                None,
+                FieldSet::new(names, Identifier(callsite)),
+                Kind::SPAN,
+            ));
+            callsite::register(callsite);
+            callsite
+        });
+
+    callsite::Callsite::metadata(callsite)
+}
+
+/// Creates a span named `operation` with the `fields`, whose values are recorded with
+/// [`Debug`](fmt::Debug), attributed to the source `location`.
+pub(crate) fn span(
+    operation: &'static str,
+    fields: &[(&'static str, &dyn fmt::Debug)],
+    location: &'static Location<'static>,
+) -> Span {
+    let metadata = metadata(
+        operation,
+        fields.iter().map(|(name, _)| *name).collect(),
+        location,
+    );
+    if !tracing::dispatcher::get_default(|dispatch| dispatch.enabled(metadata)) {
+        return Span::none();
+    }
+
+    let values: Vec<DebugValue<&dyn fmt::Debug>> = fields
+        .iter()
+        .map(|(_, value)| tracing::field::debug(*value))
+        .collect();
+    let values: Vec<Option<&dyn Value>> = values
+        .iter()
+        .map(|value| -> Option<&dyn Value> { Some(value) })
+        .collect();
+    Span::new(metadata, &metadata.fields().value_set_all(&values))
+}
+
+/// Runs `f` as the operation `operation`, so that its error carries the same context as the
+/// errors of the wrappers of this crate.
+///
+/// It is meant for the I/O made by other crates through [`std::fs`]. `f` runs in a span named
+/// `operation` with the `fields`, whose values are recorded with [`Debug`](fmt::Debug). Unlike
+/// the spans of the wrappers, the span is entered while `f` runs, so that the errors returned by
+/// the calls to fs-tracing made by `f` are traced inside it. They are returned as is, and the
+/// other errors are wrapped.
+///
+/// The span is attributed to the caller. Each combination of a caller, an operation and field
+/// names registers a callsite which is never freed, so the operation and the names should be
+/// fixed rather than computed.
+///
+/// ```no_run
+/// use std::path::Path;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let path = Path::new("archive.zip");
+/// let len = fs_tracing::wrap("archive::open", &[("path", &path)], || {
+///     let file = std::fs::File::open(path)?;
+///     Ok(file.metadata()?.len())
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+#[track_caller]
+pub fn wrap<T, F: FnOnce() -> io::Result<T>>(
+    operation: &'static str,
+    fields: &[(&'static str, &dyn fmt::Debug)],
+    f: F,
+) -> io::Result<T> {
+    span(operation, fields, Location::caller()).in_scope(|| {
+        f().map_err(|error| {
+            if Error::is_wrapped(&error) {
+                error
+            } else {
+                Error::wrap_std(error)
+            }
+        })
+    })
+}
+
+/// Wraps `error` in a new span of `operation` with `fields`. An error which is already wrapped
+/// becomes the source of the new one, keeping its own trace.
+fn wrap_error(
+    error: io::Error,
+    operation: &'static str,
+    fields: &[(&'static str, &dyn fmt::Debug)],
+    location: &'static Location<'static>,
+) -> io::Error {
+    span(operation, fields, location).in_scope(|| Error::wrap_std(error))
+}
+
+/// Attaches context to the errors of the `io::Result`s returned by other APIs than fs-tracing,
+/// such as [`std::fs`], so that they can be upgraded one call site at a time.
+///
+/// Each method wraps the error like the wrappers of this crate, in a span with the given context
+/// created inside the current spans and attributed to the caller. An error which is already
+/// wrapped, by a wrapper or by an earlier call to one of the methods, is wrapped again with the
+/// previous error as its source, so that chaining the methods nests the contexts and the error
+/// prints each trace from the innermost to the outermost. Use
+/// [`with_context`](IoResultExt::with_context) to attach an operation and several fields in a
+/// single span.
+///
+/// ```
+/// use fs_tracing::IoResultExt;
+/// use std::path::Path;
+///
+/// let e = std::fs::read("/not_exist").with_path("/not_exist").unwrap_err();
+/// println!("{}", e);
+///
+/// let path = Path::new("/not_exist");
+/// let e = std::fs::read(path)
+///     .with_context("cfg::load", &[("path", &path)])
+///     .with_operation("app::start")
+///     .unwrap_err();
+/// println!("{}", e);
+/// ```
+pub trait IoResultExt<T> {
+    /// Wraps the error in an `io` span with the `path` field.
+    fn with_path<P: AsRef<Path>>(self, path: P) -> io::Result<T>;
+
+    /// Wraps the error in an `io` span with the `from` and `to` fields.
+    fn with_paths<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<T>;
+
+    /// Wraps the error in a span named `operation`.
+    fn with_operation(self, operation: &'static str) -> io::Result<T>;
+
+    /// Wraps the error in a span named `operation` with the `fields`, whose values are recorded
+    /// with [`Debug`](fmt::Debug) as in [`wrap`].
+    fn with_context(
+        self,
+        operation: &'static str,
+        fields: &[(&'static str, &dyn fmt::Debug)],
+    ) -> io::Result<T>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    #[track_caller]
+    fn with_path<P: AsRef<Path>>(self, path: P) -> io::Result<T> {
+        let location = Location::caller();
+        self.map_err(|error| wrap_error(error, "io", &[("path", &path.as_ref())], location))
+    }
+
+    #[track_caller]
+    fn with_paths<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<T> {
+        let location = Location::caller();
+        self.map_err(|error| {
+            wrap_error(
+                error,
+                "io",
+                &[("from", &from.as_ref()), ("to", &to.as_ref())],
+                location,
+            )
+        })
+    }
+
+    #[track_caller]
+    fn with_operation(self, operation: &'static str) -> io::Result<T> {
+        let location = Location::caller();
+        self.map_err(|error| wrap_error(error, operation, &[], location))
+    }
+
+    #[track_caller]
+    fn with_context(
+        self,
+        operation: &'static str,
+        fields: &[(&'static str, &dyn fmt::Debug)],
+    ) -> io::Result<T> {
+        let location = Location::caller();
+        self.map_err(|error| wrap_error(error, operation, fields, location))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn chained_contexts_nest() {
+        let subscriber = tracing_subscriber::registry().with(tracing_error::ErrorLayer::default());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let e = crate::read("/not_exist")
+            .with_operation("wrap::tests::load")
+            .with_path("/outer")
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+
+        let message = e.to_string();
+        let read = message.find("fs_tracing::read").unwrap();
+        let load = message.find("fs_tracing::wrap::tests::load").unwrap();
+        let outer = message.find("path=\"/outer\"").unwrap();
+        assert!(read < load && load < outer, "{}", message);
+        assert!(message.contains(file!()), "{}", message);
+    }
+}